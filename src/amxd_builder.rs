@@ -16,19 +16,30 @@
 
 
 use crate::amxd_fields::{build_frozen_device_field_padless, build_header_field};
+use anyhow::{anyhow, Result};
 use bytes::{BufMut, Bytes, BytesMut};
+use clap::builder::PossibleValue;
+use clap::ValueEnum;
+use std::fmt;
 
-#[derive(Debug, Clone)]
+/// The kind of Max for Live device, as stored in the `ampf` header field.
+#[derive(Debug, Clone, PartialEq)]
 pub enum DeviceType {
+    /// Audio effect (`aaaa`)
     AudioEffect,
+    /// MIDI effect (`mmmm`)
     MidiEffect,
+    /// Instrument (`iiii`)
     Instrument,
+    /// MIDI Tool note generator (`nagg`)
     MidiToolGenerator,
+    /// MIDI Tool note transformer (`natt`)
     MidiToolTransformer,
 }
 
 impl DeviceType {
-    fn to_header_representation(&self) -> Bytes {
+    /// The four characters stored in the `ampf` field
+    pub fn to_header_representation(&self) -> Bytes {
         let name = match self {
             DeviceType::AudioEffect => { "aaaa" }
             DeviceType::MidiEffect => { "mmmm" }
@@ -39,8 +50,50 @@ impl DeviceType {
 
         Bytes::from(name)
     }
+
+    /// Reads the value of the `ampf` field
+    pub fn from_header_representation(name: &[u8]) -> Result<DeviceType> {
+        match name {
+            b"aaaa" => Ok(DeviceType::AudioEffect),
+            b"mmmm" => Ok(DeviceType::MidiEffect),
+            b"iiii" => Ok(DeviceType::Instrument),
+            b"nagg" => Ok(DeviceType::MidiToolGenerator),
+            b"natt" => Ok(DeviceType::MidiToolTransformer),
+            _ => Err(anyhow!("Unknown device type '{}'", String::from_utf8_lossy(name))),
+        }
+    }
+}
+
+impl ValueEnum for DeviceType {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::AudioEffect,
+            Self::MidiEffect,
+            Self::Instrument,
+            Self::MidiToolGenerator,
+            Self::MidiToolTransformer,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::AudioEffect => PossibleValue::new("audio-fx"),
+            Self::MidiEffect => PossibleValue::new("midi-fx"),
+            Self::Instrument => PossibleValue::new("instrument"),
+            Self::MidiToolGenerator => PossibleValue::new("note-generator"),
+            Self::MidiToolTransformer => PossibleValue::new("note-transformer"),
+        })
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.to_possible_value().map(|value| value.get_name().to_string());
+        write!(f, "{}", name.unwrap_or_default())
+    }
 }
 
+/// Assembles a frozen device from the packed file data and its footer.
 pub fn build_frozen_amxd(device_type: &DeviceType, meta: &u32, data: Bytes, footer: Bytes) -> Bytes {
     let mut buf = BytesMut::new();
 
@@ -51,16 +104,31 @@ pub fn build_frozen_amxd(device_type: &DeviceType, meta: &u32, data: Bytes, foot
     buf.freeze()
 }
 
+/// Wraps the JSON of a patcher into a device header.
+pub fn build_unfrozen_amxd(device_type: &DeviceType, meta: &u32, patcher: Bytes) -> Bytes {
+    let mut buf = BytesMut::new();
+
+    buf.put(build_header_field("ampf", device_type.to_header_representation()));
+    buf.put(build_header_field("meta", Bytes::from(meta.to_le_bytes().to_vec())));
+    buf.put(build_header_field("ptch", patcher));
+
+    buf.freeze()
+}
+
 fn build_frozen_device_body(data: Bytes, footer: Bytes) -> Bytes {
     let mut buf = BytesMut::new();
 
-    buf.put(build_frozen_header(data.len() as u32 + 16));  // 16 = frozen device header
+    buf.put(build_frozen_header((data.len() + FROZEN_HEADER_LENGTH) as u32));
     buf.put(data);
     buf.put(footer);
 
     buf.freeze()
 }
 
+/// Size of the `mx@c` header at the start of a frozen device's data region. The data offsets
+/// and the footer location of a frozen device count from the start of this header.
+pub const FROZEN_HEADER_LENGTH: usize = 16;
+
 fn build_frozen_header(footer_location: u32) -> Bytes {
     let mut buf = BytesMut::new();
 
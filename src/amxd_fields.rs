@@ -15,19 +15,40 @@
  */
 
 
-use bytes::{BufMut, Bytes, BytesMut};
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
+/// A field of the device header or of a frozen device: a four character type and its data.
+pub struct Field {
+    pub field_type: String,
+    pub data: Bytes,
+}
+
+/// Fields of a frozen device store a big endian length that includes the 8 byte descriptor,
+/// and are padded to a multiple of 4 bytes.
 pub fn build_frozen_device_field(field_type: &str, data: Bytes) -> Bytes {
     build_field(field_type, data, true, true, true)
 }
+/// Like [`build_frozen_device_field`], without the padding. Used for the `mx@c` header.
 pub fn build_frozen_device_field_padless(field_type: &str, data: Bytes) -> Bytes {
     build_field(field_type, data, true, true, false)
 }
 
+/// Fields of the device header store a little endian length of the data alone.
 pub fn build_header_field(field_type: &str, data: Bytes) -> Bytes {
     build_field(field_type, data, false, false, true)
 }
 
+/// Reads a field written by [`build_frozen_device_field`], advancing the buffer past it.
+pub fn read_frozen_device_field(buf: &mut Bytes) -> Result<Field> {
+    read_field(buf, true, true)
+}
+
+/// Reads a field written by [`build_header_field`], advancing the buffer past it.
+pub fn read_header_field(buf: &mut Bytes) -> Result<Field> {
+    read_field(buf, false, false)
+}
+
 fn build_field(
     field_type: &str,
     data: Bytes,
@@ -58,3 +79,32 @@ fn build_field(
 
     buf.freeze()
 }
+
+
+/// Reads a field written by `build_field` with the same layout options.
+/// The returned data still contains any padding that was added to the end.
+fn read_field(buf: &mut Bytes, big_endian: bool, include_desc_length: bool) -> Result<Field> {
+    if buf.remaining() < 8 {
+        bail!("Unexpected end of data while reading field descriptor");
+    }
+
+    let field_type = String::from_utf8_lossy(&buf.split_to(4)).to_string();
+    let data_length = if big_endian { buf.get_u32() } else { buf.get_u32_le() } as usize;
+
+    let remove_length = if include_desc_length { 8 } else { 0 };
+    if data_length < remove_length {
+        bail!("Invalid length {} for field '{}'", data_length, field_type);
+    }
+
+    let data_length = data_length - remove_length;
+    if buf.remaining() < data_length {
+        bail!(
+            "Field '{}' is truncated: expected {} bytes, found {}",
+            field_type,
+            data_length,
+            buf.remaining()
+        );
+    }
+
+    Ok(Field { field_type, data: buf.split_to(data_length) })
+}
@@ -15,11 +15,15 @@
  */
 
 
-use std::time::SystemTime;
-use bytes::{BufMut, Bytes, BytesMut};
-use crate::amxd_fields::{build_frozen_device_field};
+use std::time::{Duration, SystemTime};
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use crate::amxd_fields::{build_frozen_device_field, read_frozen_device_field};
 use crate::device_builder::{DeviceFile, DeviceFileFlag};
 
+const HFSPLUS_OFFSET: u64 = 2082844800;
+
+/// Builds the `dlst` footer with one `dire` entry per file.
 pub fn build_footer(files: &[DeviceFile]) -> Bytes{
     let mut buf = BytesMut::new();
 
@@ -30,21 +34,96 @@ pub fn build_footer(files: &[DeviceFile]) -> Bytes{
     build_frozen_device_field("dlst", buf.freeze())
 }
 
+/// Reads the `dlst` footer of a frozen device, failing on the first malformed entry.
+pub fn parse_footer(mut footer: Bytes) -> Result<Vec<DeviceFile>> {
+    let dlst = read_frozen_device_field(&mut footer)?;
+    if dlst.field_type != "dlst" {
+        bail!("Expected 'dlst' field in footer, found '{}'", dlst.field_type);
+    }
+
+    let mut entries = dlst.data;
+    let mut files = Vec::new();
+    while entries.has_remaining() {
+        let dire = read_frozen_device_field(&mut entries)?;
+        if dire.field_type != "dire" {
+            bail!("Expected 'dire' field in footer, found '{}'", dire.field_type);
+        }
+
+        files.push(DeviceFile::from_footer_field(dire.data)?);
+    }
+
+    Ok(files)
+}
+
+/// Reads a damaged footer: the `dire` entries are read one at a time, up to the point where the
+/// footer is cut off, and entries that are incomplete are skipped. Returns the entries that could
+/// be read, and what was wrong with the rest.
+pub fn parse_footer_leniently(mut footer: Bytes) -> (Vec<DeviceFile>, Vec<String>) {
+    let mut problems = Vec::new();
+    if footer.remaining() < 8 || !footer.starts_with(b"dlst") {
+        problems.push("has no 'dlst' field".to_string());
+        return (Vec::new(), problems);
+    }
+
+    footer.advance(4);
+    let length = (footer.get_u32() as usize).saturating_sub(8);
+    if length > footer.remaining() {
+        problems.push(format!("is truncated: expected {} bytes, found {}", length, footer.remaining()));
+    }
+    let mut entries = footer.split_to(length.min(footer.remaining()));
+
+    let mut files = Vec::new();
+    while entries.has_remaining() {
+        let dire = match read_frozen_device_field(&mut entries) {
+            Ok(dire) if dire.field_type == "dire" => dire,
+            Ok(field) => {
+                problems.push(format!("has an unexpected '{}' field", field.field_type));
+                break;
+            }
+            Err(e) => {
+                problems.push(format!("ends with an incomplete entry ({:#})", e));
+                break;
+            }
+        };
+
+        match DeviceFile::from_footer_field(dire.data) {
+            Ok(file) => files.push(file),
+            Err(e) => problems.push(format!("has an invalid entry ({:#})", e)),
+        }
+    }
+
+    (files, problems)
+}
+
 impl DeviceFileFlag {
-    fn to_bytes(&self) -> Bytes {
-        let raw_flag: u32 = match self {
+    /// The value stored in the `flag` field
+    pub fn to_raw(&self) -> u32 {
+        match self {
             DeviceFileFlag::None => { 0 }
             DeviceFileFlag::JSFile => { 8 }
             DeviceFileFlag::MainFile => { 17 }
-        };
+            DeviceFileFlag::Other(raw_flag) => { *raw_flag }
+        }
+    }
+
+    fn to_bytes(&self) -> Bytes {
+        Bytes::from(self.to_raw().to_be_bytes().to_vec())
+    }
 
-        Bytes::from(raw_flag.to_be_bytes().to_vec())
+    /// Unknown values are kept as [`DeviceFileFlag::Other`]
+    pub fn from_raw(raw_flag: u32) -> Self {
+        match raw_flag {
+            0 => DeviceFileFlag::None,
+            8 => DeviceFileFlag::JSFile,
+            17 => DeviceFileFlag::MainFile,
+            _ => DeviceFileFlag::Other(raw_flag),
+        }
     }
 }
 
 impl DeviceFile {
     fn to_footer_field(&self) -> Bytes {
-        let hfsplus_time:u32 = to_hfsplus_time(&self.modification_date);
+        let hfsplus_time:u32 = to_hfsplus_time_clamped(&self.modification_date);
 
         let mut buf = BytesMut::new();
         buf.put(build_frozen_device_field("type", Bytes::from(self.file_type.to_owned())));
@@ -53,15 +132,93 @@ impl DeviceFile {
         buf.put(build_frozen_device_field("of32", Bytes::from(self.data_offset.to_be_bytes().to_vec())));
         buf.put(build_frozen_device_field("flag", self.flag.to_bytes()));
         buf.put(build_frozen_device_field("mdat", Bytes::from(hfsplus_time.to_be_bytes().to_vec())));
-        buf.put(build_frozen_device_field("vers", Bytes::from(0u32.to_be_bytes().to_vec())));
+        buf.put(build_frozen_device_field("vers", Bytes::from(self.version.to_be_bytes().to_vec())));
 
         build_frozen_device_field("dire", buf.freeze())
     }
+
+    fn from_footer_field(mut data: Bytes) -> Result<DeviceFile> {
+        let mut file_type = None;
+        let mut file_name = None;
+        let mut data_size = None;
+        let mut data_offset = None;
+        let mut flag = None;
+        let mut modification_date = None;
+        let mut version = None;
+
+        while data.has_remaining() {
+            let field = read_frozen_device_field(&mut data)?;
+            match field.field_type.as_str() {
+                "type" => file_type = Some(read_string(&field.data)),
+                "fnam" => file_name = Some(read_string(&field.data)),
+                "sz32" => data_size = Some(read_u32(&field.data)?),
+                "of32" => data_offset = Some(read_u32(&field.data)?),
+                "flag" => flag = Some(DeviceFileFlag::from_raw(read_u32(&field.data)?)),
+                "mdat" => modification_date = Some(from_hfsplus_time(read_u32(&field.data)?)),
+                "vers" => version = Some(read_u32(&field.data)?),
+                _ => {}
+            }
+        }
+
+        Ok(DeviceFile {
+            file_type: file_type.ok_or_else(|| anyhow!("File entry is missing 'type'"))?,
+            file_name: file_name.ok_or_else(|| anyhow!("File entry is missing 'fnam'"))?,
+            data_size: data_size.ok_or_else(|| anyhow!("File entry is missing 'sz32'"))?,
+            data_offset: data_offset.ok_or_else(|| anyhow!("File entry is missing 'of32'"))?,
+            flag: flag.unwrap_or(DeviceFileFlag::None),
+            modification_date: modification_date.unwrap_or(SystemTime::UNIX_EPOCH),
+            version: version.unwrap_or(0),
+        })
+    }
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+fn read_u32(data: &[u8]) -> Result<u32> {
+    let bytes: [u8; 4] = data.get(..4)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| anyhow!("Expected a 4 byte value, found {} bytes", data.len()))?;
+
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn from_hfsplus_time(hfsplus_time: u32) -> SystemTime {
+    match (hfsplus_time as u64).checked_sub(HFSPLUS_OFFSET) {
+        Some(unix_time) => SystemTime::UNIX_EPOCH + Duration::from_secs(unix_time),
+        None => SystemTime::UNIX_EPOCH - Duration::from_secs(HFSPLUS_OFFSET - hfsplus_time as u64),
+    }
 }
 
-fn to_hfsplus_time(system_time: &SystemTime) -> u32 {
-    const HFSPLUS_OFFSET: u64 = 2082844800;
+/// The first second an HFS+ timestamp can hold, 1904-01-01.
+pub fn hfsplus_epoch() -> SystemTime {
+    from_hfsplus_time(0)
+}
+
+/// Seconds since 1904-01-01, rounded down
+fn hfsplus_seconds(system_time: &SystemTime) -> i128 {
+    let unix_time = match system_time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i128,
+        Err(e) => -(e.duration().as_secs() as i128) - i128::from(e.duration().subsec_nanos() > 0),
+    };
+
+    unix_time + HFSPLUS_OFFSET as i128
+}
+
+/// HFS+ timestamps count seconds since 1904 in 32 bits, so they end on 2040-02-06.
+/// Returns `None` for times outside of that range.
+pub fn to_hfsplus_time(system_time: &SystemTime) -> Option<u32> {
+    u32::try_from(hfsplus_seconds(system_time)).ok()
+}
+
+/// Like [`to_hfsplus_time`], but times outside of the range become its first or last second.
+pub fn to_hfsplus_time_clamped(system_time: &SystemTime) -> u32 {
+    hfsplus_seconds(system_time).clamp(0, u32::MAX as i128) as u32
+}
 
-    let unix_time = system_time.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
-    (unix_time + HFSPLUS_OFFSET) as u32
+/// Returns the time that is actually stored in the footer for the given time.
+pub fn clamp_to_hfsplus_range(system_time: &SystemTime) -> SystemTime {
+    from_hfsplus_time(to_hfsplus_time_clamped(system_time))
 }
\ No newline at end of file
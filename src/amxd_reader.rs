@@ -0,0 +1,164 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use crate::amxd_builder::DeviceType;
+use crate::amxd_fields::{read_frozen_device_field, read_header_field};
+use crate::amxd_footer::parse_footer;
+use crate::device_builder::{DeviceFile, DeviceFileFlag};
+use crate::maxpat_project::parse_patcher_data;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{Buf, Bytes};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A parsed `.amxd` file.
+pub struct AmxdFile {
+    pub device_type: DeviceType,
+    pub meta: u32,
+    pub patcher: AmxdPatcher,
+}
+
+/// The `ptch` field of a device.
+pub enum AmxdPatcher {
+    /// The JSON of the patcher, as written by Max
+    Unfrozen(Bytes),
+    Frozen(FrozenDevice),
+}
+
+/// A device with embedded files.
+pub struct FrozenDevice {
+    /// The complete `ptch` payload, starting with the `mx@c` header.
+    /// File offsets in the footer are relative to the start of this buffer.
+    pub body: Bytes,
+    pub footer_location: u32,
+    pub files: Vec<DeviceFile>,
+}
+
+impl AmxdFile {
+    /// Returns the raw JSON of the main patcher, regardless of whether the device is frozen.
+    pub fn patcher_data(&self) -> Result<Bytes> {
+        match &self.patcher {
+            AmxdPatcher::Unfrozen(patcher) => Ok(patcher.clone()),
+            AmxdPatcher::Frozen(frozen_device) => {
+                let main_file = frozen_device.main_file()
+                    .ok_or_else(|| anyhow!("Frozen device does not contain a main patcher"))?;
+                frozen_device.file_data(main_file)
+            }
+        }
+    }
+
+    /// Parses the JSON of the main patcher.
+    pub fn patcher_json(&self) -> Result<Value> {
+        parse_patcher_data(&self.patcher_data()?).context("Main patcher is not valid JSON")
+    }
+}
+
+impl FrozenDevice {
+    /// The file flagged as the main patcher
+    pub fn main_file(&self) -> Option<&DeviceFile> {
+        self.files.iter().find(|file| file.flag == DeviceFileFlag::MainFile)
+    }
+
+    /// The data of an embedded file, failing if its entry points outside of the device
+    pub fn file_data(&self, file: &DeviceFile) -> Result<Bytes> {
+        let start = file.data_offset as usize;
+        let end = start + file.data_size as usize;
+        if end > self.body.len() {
+            bail!("Data of file '{}' lies outside of the device ({}..{})", file.file_name, start, end);
+        }
+
+        Ok(self.body.slice(start..end))
+    }
+}
+
+/// Reads and parses a device file.
+pub fn read_amxd(path: &str) -> Result<AmxdFile> {
+    let data = fs::read(path).with_context(|| format!("Cannot read device file '{}'", path))?;
+    parse_amxd(Bytes::from(data)).with_context(|| format!("Cannot parse device file '{}'", path))
+}
+
+/// Parses a frozen or unfrozen device, including the footer of a frozen one.
+pub fn parse_amxd(mut data: Bytes) -> Result<AmxdFile> {
+    if !data.starts_with(b"ampf") {
+        if data.trim_ascii_start().starts_with(b"{") {
+            bail!("File is a plain patcher, not a device (missing 'ampf' header)");
+        }
+        bail!("File is not a Max for Live device (missing 'ampf' header)");
+    }
+
+    let mut device_type = None;
+    let mut meta = None;
+    let mut patcher = None;
+
+    while data.has_remaining() && patcher.is_none() {
+        let field = read_header_field(&mut data).context("Device header is malformed")?;
+        match field.field_type.as_str() {
+            "ampf" => device_type = Some(DeviceType::from_header_representation(&field.data)?),
+            "meta" => meta = Some(read_meta(&field.data)?),
+            "ptch" => patcher = Some(parse_patcher(field.data)?),
+            _ => {}
+        }
+    }
+
+    Ok(AmxdFile {
+        device_type: device_type.ok_or_else(|| anyhow!("Device is missing the 'ampf' field"))?,
+        meta: meta.ok_or_else(|| anyhow!("Device is missing the 'meta' field"))?,
+        patcher: patcher.ok_or_else(|| anyhow!("Device is missing the 'ptch' field"))?,
+    })
+}
+
+fn parse_patcher(body: Bytes) -> Result<AmxdPatcher> {
+    if !body.starts_with(b"mx@c") {
+        return Ok(AmxdPatcher::Unfrozen(body));
+    }
+
+    let mut header_data = body.clone();
+    let header = read_frozen_device_field(&mut header_data)?;
+    if header.data.len() < 8 {
+        bail!("Frozen device header is truncated");
+    }
+
+    let footer_location = header.data.clone().get_u64() as usize;
+    if footer_location > body.len() {
+        bail!("Footer location {} lies outside of the device", footer_location);
+    }
+
+    let files = parse_footer(body.slice(footer_location..))?;
+
+    Ok(AmxdPatcher::Frozen(FrozenDevice { body, footer_location: footer_location as u32, files }))
+}
+
+/// Returns the path for extracting an embedded file into a directory, refusing names that
+/// would end up outside of it.
+pub fn output_file_path(output_dir: &str, file_name: &str) -> Result<PathBuf> {
+    let name = Path::new(file_name);
+    if file_name.is_empty() || name.file_name() != Some(name.as_os_str()) {
+        bail!("Refusing to extract file with invalid name '{}'", file_name);
+    }
+
+    Ok(Path::new(output_dir).join(name))
+}
+
+fn read_meta(data: &Bytes) -> Result<u32> {
+    if data.len() != 4 {
+        bail!("Field 'meta' has an invalid length of {} bytes", data.len());
+    }
+
+    Ok(data.clone().get_u32_le())
+}
@@ -15,46 +15,248 @@
  */
 
 
-use clap::{Parser, ValueEnum};
-use clap::builder::PossibleValue;
-use crate::amxd_builder::DeviceType;
+use clap::{Args, Parser, Subcommand};
+use maxbuild::device_builder::utc_time;
+use maxbuild::{DeviceType, ModificationDate};
+use std::time::{Duration, SystemTime};
 
 #[derive(Parser, Debug)]
-#[command(version = "0.0.1")]
+#[command(version = "0.0.1", args_conflicts_with_subcommands = true)]
 pub struct MaxBuildArgs {
-    #[arg(short, long, required = true)]
-    pub template: String,
+    #[command(subcommand)]
+    pub command: Option<MaxBuildCommand>,
 
-    #[arg(short, long, required = true)]
-    pub output_file: String,
+    #[command(flatten)]
+    pub build: Option<BuildArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MaxBuildCommand {
+    /// Extract the main patcher and all embedded files of a frozen device
+    Unfreeze(UnfreezeArgs),
+
+    /// Print the header and the list of embedded files of a device
+    #[command(visible_alias = "list")]
+    Inspect(InspectArgs),
+
+    /// Compare the headers, embedded files and main patchers of two devices
+    Diff(DiffArgs),
+
+    /// Print a stable text rendering of a device, for use as a git textconv driver
+    Textconv(TextconvArgs),
+
+    /// Add, replace or remove embedded files of a frozen device in place
+    Update(UpdateArgs),
+
+    /// Write a single embedded file of a frozen device to a file or stdout
+    Extract(ExtractArgs),
+
+    /// Check a device for structural problems, exiting with a non-zero code if any are found
+    Verify(VerifyArgs),
+
+    /// Recover the contents of a frozen device with a broken footer or inconsistent offsets
+    Repair(RepairArgs),
+
+    /// List included files that nothing refers to and references that can't be resolved
+    CheckDeps(CheckDepsArgs),
+}
+
+#[derive(Args, Debug, Default)]
+pub struct BuildArgs {
+    /// Project file to read the build settings from [default: maxbuild.toml in the current or a parent directory]
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    #[arg(short, long)]
+    pub template: Option<String>,
+
+    #[arg(short, long)]
+    pub output_file: Option<String>,
 
+    /// File or folder to pack into the device (can be repeated). Use <path>=<name> to embed a file under another name
     #[arg(short, long)]
     pub include: Vec<String>,
-    
+
+    /// Read a file from stdin and pack it under the given name
+    #[arg(long, value_name = "NAME")]
+    pub include_stdin: Option<String>,
+
+    /// Zip archive whose files are packed into the device, optionally limited to <archive>#<sub-path> (can be repeated)
+    #[arg(long)]
+    pub include_zip: Vec<String>,
+
+    /// Glob pattern of files to leave out, matched against the path relative to the include root (can be repeated)
+    #[arg(short, long)]
+    pub exclude: Vec<String>,
+
+    /// Also pack hidden files (starting with '.') found in included directories
+    #[arg(long)]
+    pub include_hidden: bool,
+
+    /// The type of the device [default: the type of the template]
+    #[arg(short, long)]
+    pub device_type: Option<DeviceType>,
+
+    /// Use the given device type even if it doesn't match the template
+    #[arg(long)]
+    pub force_device_type: bool,
+
+    /// The value of the 'meta' header field [default: the value of the template, or 0 for plain patchers]
+    #[arg(long)]
+    pub meta: Option<u32>,
+
+    /// Also pack the abstractions, scripts, gen patchers, media and pictures the template refers to
+    #[arg(long)]
+    pub discover_dependencies: bool,
+
+    /// Directory to look for dependencies in (can be repeated) [default: the directory of the template]
+    #[arg(long)]
+    pub search_root: Vec<String>,
+
+    /// Date stored for the packed files: 'source' (modification time of each file), 'build' (build time),
+    /// seconds since 1970 or a UTC date like 2024-05-01T12:00:00Z [default: $SOURCE_DATE_EPOCH if set, else 'source']
+    #[arg(long, value_parser = parse_modification_date)]
+    pub modification_date: Option<ModificationDate>,
+
+    /// Only build the given devices of a workspace (can be repeated)
+    #[arg(long)]
+    pub target: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckDepsArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    /// File with the names of the built-in Max and Live objects, one per line [default: the list shipped with maxbuild]
+    #[arg(long)]
+    pub builtins: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct UnfreezeArgs {
+    /// The frozen device to extract
+    pub device: String,
+
     #[arg(short, long, required = true)]
-    pub device_type: DeviceType,
+    pub output_dir: String,
 }
 
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    /// The device to inspect
+    pub device: String,
 
+    /// Print the contents as JSON
+    #[arg(long)]
+    pub json: bool,
+}
 
-impl ValueEnum for DeviceType {
-    fn value_variants<'a>() -> &'a [Self] {
-        &[
-            Self::AudioEffect,
-            Self::MidiEffect,
-            Self::Instrument,
-            Self::MidiToolGenerator,
-            Self::MidiToolTransformer,
-        ]
-    }
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The original device
+    pub old_device: String,
+
+    /// The changed device
+    pub new_device: String,
+}
+
+#[derive(Args, Debug)]
+pub struct UpdateArgs {
+    /// The frozen device to update
+    pub device: String,
+
+    /// Add a file, replacing an embedded file with the same name (can be repeated)
+    #[arg(short, long)]
+    pub add: Vec<String>,
+
+    /// Remove the embedded file with the given name (can be repeated)
+    #[arg(short, long)]
+    pub remove: Vec<String>,
+
+    /// Write the result to this file instead of updating the device in place
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Allow replacing or removing the main patcher of the device
+    #[arg(long)]
+    pub allow_main_file: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+    /// The frozen device to extract from
+    pub device: String,
+
+    /// The name of the embedded file
+    #[arg(required_unless_present = "main", conflicts_with = "main")]
+    pub name: Option<String>,
+
+    /// Extract the main patcher of the device
+    #[arg(long)]
+    pub main: bool,
 
-    fn to_possible_value(&self) -> Option<PossibleValue> {
-        Some(match self {
-            Self::AudioEffect => PossibleValue::new("audio-fx"),
-            Self::MidiEffect => PossibleValue::new("midi-fx"),
-            Self::Instrument => PossibleValue::new("instrument"),
-            Self::MidiToolGenerator => PossibleValue::new("note-generator"),
-            Self::MidiToolTransformer => PossibleValue::new("note-transformer"),
-        })
+    /// Write the file to this path instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// The device to verify
+    pub device: String,
+}
+
+#[derive(Args, Debug)]
+pub struct RepairArgs {
+    /// The broken frozen device
+    pub device: String,
+
+    /// Where to write the repaired device [default: <device>.repaired.amxd]
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct TextconvArgs {
+    /// The device to render
+    pub device: String,
+}
+
+pub fn parse_modification_date(value: &str) -> Result<ModificationDate, String> {
+    match value {
+        "source" => return Ok(ModificationDate::Source),
+        "build" => return Ok(ModificationDate::BuildTime),
+        _ => {}
     }
-}
\ No newline at end of file
+
+    let modification_date = match value.parse::<u64>() {
+        Ok(seconds) => SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(seconds)),
+        Err(_) => parse_utc_date(value),
+    };
+
+    modification_date
+        .map(ModificationDate::Fixed)
+        .ok_or_else(|| format!("'{}' is neither 'source', 'build', a number of seconds nor a date like 2024-05-01T12:00:00Z", value))
+}
+
+/// Parses `YYYY-MM-DD`, optionally followed by `THH:MM:SS` and `Z`
+fn parse_utc_date(value: &str) -> Option<SystemTime> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = value.split_once('T').unwrap_or((value, "00:00:00"));
+
+    let date: Vec<&str> = date.split('-').collect();
+    let time: Vec<&str> = time.split(':').collect();
+    let ([year, month, day], [hour, minute, second]) = (date.as_slice(), time.as_slice()) else {
+        return None;
+    };
+
+    utc_time(
+        year.parse().ok()?,
+        month.parse().ok()?,
+        day.parse().ok()?,
+        hour.parse().ok()?,
+        minute.parse().ok()?,
+        second.parse().ok()?,
+    )
+}
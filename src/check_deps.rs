@@ -0,0 +1,63 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+use maxbuild::dependencies::{parse_builtin_objects, DEFAULT_BUILTIN_OBJECTS};
+use crate::args::CheckDepsArgs;
+use crate::create_builder;
+use crate::project_config::resolve_build_settings;
+use anyhow::{bail, Context, Result};
+use std::fs;
+
+pub fn check_dependencies(args: CheckDepsArgs) -> Result<()> {
+    let builtin_objects = match &args.builtins {
+        Some(path) => parse_builtin_objects(&fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the built-in object list '{}'", path))?),
+        None => parse_builtin_objects(DEFAULT_BUILTIN_OBJECTS),
+    };
+
+    let mut problem_count = 0;
+    for target in resolve_build_settings(args.build)? {
+        let report = create_builder(&target)?.check_dependencies(&builtin_objects)?;
+        println!("Device: {}", target.name);
+        if !report.unreferenced.is_empty() {
+            println!("  Unreferenced includes:");
+            for source in &report.unreferenced {
+                println!("    {}", source);
+            }
+        }
+        if !report.missing.is_empty() {
+            println!("  Missing references:");
+            for missing in &report.missing {
+                match &missing.found_at {
+                    Some(path) => println!("    {} '{}' (found at {} but not included)", missing.kind, missing.name, path.display()),
+                    None => println!("    {} '{}'", missing.kind, missing.name),
+                }
+            }
+        }
+        if report.unreferenced.is_empty() && report.missing.is_empty() {
+            println!("  All dependencies are included");
+        }
+
+        problem_count += report.unreferenced.len() + report.missing.len();
+    }
+
+    if problem_count > 0 {
+        bail!("Found {} dependency problem(s)", problem_count);
+    }
+
+    Ok(())
+}
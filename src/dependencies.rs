@@ -0,0 +1,310 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+use crate::device_builder::collision_key;
+use crate::max_filetypes::determine_file_type;
+use crate::maxpat_project::parse_patcher_data;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+/// What a patcher refers to a file for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DependencyKind {
+    /// An object box that may be an abstraction, or a `bpatcher`, `poly~` or `pfft~` patcher
+    Abstraction,
+    /// A script of `js`, `jsui`, `v8`, `v8ui` or `node.script`
+    Script,
+    /// A `gen~`, `jit.gen` or `jit.pix` patcher
+    Gen,
+    /// An audio file of `buffer~`, `sfplay~` and similar objects
+    Media,
+    /// A picture of `fpic`, `pictctrl`, `live.text` and similar objects
+    Image,
+}
+
+impl Display for DependencyKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DependencyKind::Abstraction => "abstraction",
+            DependencyKind::Script => "script",
+            DependencyKind::Gen => "gen",
+            DependencyKind::Media => "media",
+            DependencyKind::Image => "image",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A file name found in a patcher.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    /// The name as written in the patcher, e.g. an object name or a file name
+    pub name: String,
+    pub kind: DependencyKind,
+    /// The file names Max would look for on its search path, in order
+    pub candidates: Vec<String>,
+}
+
+/// Object boxes whose first argument names a patcher
+const PATCHER_LOADERS: &[&str] = &["poly~", "mc.poly~", "pfft~", "mc.pfft~"];
+const SCRIPT_OBJECTS: &[&str] = &["js", "jsui", "v8", "v8ui", "node.script"];
+const GEN_OBJECTS: &[(&str, &str)] = &[
+    ("gen~", "gendsp"),
+    ("mc.gen~", "gendsp"),
+    ("gen", "genjit"),
+    ("jit.gen", "genjit"),
+    ("jit.pix", "genjit"),
+    ("jit.gl.pix", "genjit"),
+];
+const MEDIA_OBJECTS: &[&str] = &["buffer~", "polybuffer~", "sfplay~", "mc.sfplay~", "playlist~", "sflist~"];
+/// Box attributes that hold the name of a picture
+const IMAGE_ATTRIBUTES: &[&str] = &["pic", "pic2", "pictures", "bkgndpict", "knobpict", "cellpict"];
+
+/// Collects the references of a patcher, including its embedded subpatchers.
+pub fn find_references(patcher_json: &Value) -> Vec<Reference> {
+    let mut references = Vec::new();
+    if let Some(patcher) = patcher_json.get("patcher") {
+        collect_references(patcher, &mut references);
+    }
+
+    references
+}
+
+fn collect_references(patcher: &Value, references: &mut Vec<Reference>) {
+    let boxes = patcher["boxes"].as_array().map(Vec::as_slice).unwrap_or_default();
+    for object in boxes.iter().map(|object| &object["box"]) {
+        let maxclass = object["maxclass"].as_str().unwrap_or_default();
+        let text = object["text"].as_str().unwrap_or_default();
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let class_name = if maxclass == "newobj" { tokens.first().copied().unwrap_or_default() } else { maxclass };
+        let arguments = if maxclass == "newobj" { tokens.get(1..).unwrap_or_default() } else { &[] };
+        let embedded_patcher = object.get("patcher").filter(|patcher| patcher.is_object());
+
+        if let Some(&(_, extension)) = GEN_OBJECTS.iter().find(|(name, _)| *name == class_name) {
+            // Gen patchers don't contain Max objects, so embedded ones are not searched
+            if embedded_patcher.is_none() {
+                let name = attribute(arguments, "@gen").or_else(|| first_argument(arguments));
+                push_reference(references, name, DependencyKind::Gen, &[extension]);
+            }
+            continue;
+        }
+
+        if let Some(embedded_patcher) = embedded_patcher {
+            collect_references(embedded_patcher, references);
+        } else if maxclass == "bpatcher" {
+            push_reference(references, object["name"].as_str(), DependencyKind::Abstraction, &["maxpat"]);
+        } else if PATCHER_LOADERS.contains(&class_name) {
+            push_reference(references, first_argument(arguments), DependencyKind::Abstraction, &["maxpat"]);
+        } else if maxclass == "newobj" && !class_name.is_empty() && !SCRIPT_OBJECTS.contains(&class_name) {
+            push_reference(references, Some(class_name), DependencyKind::Abstraction, &["maxpat"]);
+        }
+
+        if SCRIPT_OBJECTS.contains(&class_name) {
+            let name = object["filename"].as_str()
+                .or_else(|| attribute(arguments, "@filename"))
+                .or_else(|| first_argument(arguments));
+            push_reference(references, name, DependencyKind::Script, &["js"]);
+        }
+
+        if MEDIA_OBJECTS.contains(&class_name) {
+            for argument in arguments.iter().filter(|argument| is_audio_file(argument)) {
+                push_reference(references, Some(argument), DependencyKind::Media, &[]);
+            }
+        }
+
+        for key in IMAGE_ATTRIBUTES.iter().copied().chain((maxclass == "pictctrl").then_some("name")) {
+            match &object[key] {
+                Value::String(name) => push_reference(references, Some(name), DependencyKind::Image, &[]),
+                Value::Array(names) => {
+                    for name in names.iter().filter_map(Value::as_str) {
+                        push_reference(references, Some(name), DependencyKind::Image, &[]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn first_argument<'a>(arguments: &[&'a str]) -> Option<&'a str> {
+    arguments.first().copied().filter(|argument| !argument.starts_with('@'))
+}
+
+fn attribute<'a>(arguments: &[&'a str], name: &str) -> Option<&'a str> {
+    let position = arguments.iter().position(|argument| *argument == name)?;
+    arguments.get(position + 1).copied()
+}
+
+fn is_audio_file(name: &str) -> bool {
+    Path::new(name).extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| determine_file_type(extension).project_file_type == "audiofile")
+}
+
+/// Paths in patchers may be absolute or relative, but Max looks up files by name only.
+/// Names without an extension are looked up with each of the default extensions.
+fn push_reference(references: &mut Vec<Reference>, name: Option<&str>, kind: DependencyKind, default_extensions: &[&str]) {
+    let Some(name) = name.map(|name| name.trim_matches('"')).filter(|name| !name.is_empty()) else {
+        return;
+    };
+    let Some(file_name) = Path::new(name).file_name().and_then(|file_name| file_name.to_str()) else {
+        return;
+    };
+
+    let candidates = if Path::new(file_name).extension().is_some() || default_extensions.is_empty() {
+        vec![file_name.to_string()]
+    } else {
+        default_extensions.iter().map(|extension| format!("{}.{}", file_name, extension)).collect()
+    };
+
+    references.push(Reference { name: name.to_string(), kind, candidates });
+}
+
+/// The files of a set of directories, looked up by name like on the Max search path.
+pub struct SearchPath {
+    files: BTreeMap<String, PathBuf>,
+}
+
+impl SearchPath {
+    /// Indexes the given files. If several files share a name, the first one wins.
+    pub fn new(files: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut indexed_files = BTreeMap::new();
+        for path in files {
+            if let Some(file_name) = path.file_name().and_then(|file_name| file_name.to_str()) {
+                indexed_files.entry(collision_key(file_name)).or_insert(path);
+            }
+        }
+
+        Self { files: indexed_files }
+    }
+
+    pub fn find(&self, file_name: &str) -> Option<&Path> {
+        self.files.get(&collision_key(file_name)).map(PathBuf::as_path)
+    }
+}
+
+/// A file that is already part of the device, e.g. because it was included.
+pub struct KnownFile {
+    pub name: String,
+    /// The parsed file, if it is a patcher whose references should be followed
+    pub patcher: Option<Value>,
+}
+
+/// The result of following the references of a set of patchers.
+#[derive(Default)]
+pub struct DependencyClosure {
+    /// Files found on the search path, in the order they were discovered, with the first reference to them
+    pub found: Vec<(Reference, PathBuf)>,
+    /// References that matched neither a known file nor a file on the search path
+    pub unresolved: Vec<Reference>,
+    /// References that matched one of the known files, with the name of that file
+    pub known: Vec<(Reference, String)>,
+}
+
+/// Follows the references of the given patchers. References to one of the known files count as
+/// resolved. Patchers among the known files and on the search path are searched for references as well.
+pub fn resolve_dependencies(patchers: &[Value], known_files: &[KnownFile], search_path: &SearchPath) -> DependencyClosure {
+    let known_files: BTreeMap<String, &KnownFile> = known_files.iter().map(|file| (collision_key(&file.name), file)).collect();
+    let mut closure = DependencyClosure::default();
+    let mut visited_known_files: BTreeSet<String> = BTreeSet::new();
+    let mut visited_paths: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut pending: Vec<Reference> = patchers.iter().flat_map(find_references).collect();
+    pending.reverse();
+
+    while let Some(reference) = pending.pop() {
+        let known_file = reference.candidates.iter().find_map(|name| known_files.get(&collision_key(name)));
+        if let Some(known_file) = known_file {
+            closure.known.push((reference.clone(), known_file.name.clone()));
+            if let Some(patcher_json) = known_file.patcher.as_ref().filter(|_| visited_known_files.insert(collision_key(&known_file.name))) {
+                push_nested_references(&mut pending, patcher_json);
+            }
+            continue;
+        }
+
+        let Some(path) = reference.candidates.iter().find_map(|name| search_path.find(name)) else {
+            closure.unresolved.push(reference);
+            continue;
+        };
+        if !visited_paths.insert(path.to_path_buf()) {
+            continue;
+        }
+
+        closure.found.push((reference, path.to_path_buf()));
+        if let Some(patcher_json) = read_patcher(path) {
+            push_nested_references(&mut pending, &patcher_json);
+        }
+    }
+
+    closure
+}
+
+/// References of nested patchers are handled before the remaining ones of their parent.
+fn push_nested_references(pending: &mut Vec<Reference>, patcher_json: &Value) {
+    let mut nested_references = find_references(patcher_json);
+    nested_references.reverse();
+    pending.extend(nested_references);
+}
+
+/// Returns whether a file name is one of a patcher (`.maxpat` / `.json`).
+pub fn is_patcher_file(file_name: &str) -> bool {
+    let extension = Path::new(file_name).extension().and_then(|extension| extension.to_str());
+    matches!(extension.map(|extension| extension.to_ascii_lowercase()).as_deref(), Some("maxpat" | "json"))
+}
+
+/// Parses the JSON of a patcher, `None` if it isn't valid.
+pub fn parse_patcher_json(data: &[u8]) -> Option<Value> {
+    parse_patcher_data(data).ok()
+}
+
+/// Reads a patcher file found on the search path. Other files are not searched.
+fn read_patcher(path: &Path) -> Option<Value> {
+    if !is_patcher_file(path.file_name()?.to_str()?) {
+        return None;
+    }
+
+    parse_patcher_json(&fs::read(path).ok()?)
+}
+
+/// The objects that come with Max and Live, one name per line. Used when no other list is given.
+pub const DEFAULT_BUILTIN_OBJECTS: &str = include_str!("builtin_objects.txt");
+
+/// Parses a list of built-in object names, one per line. Empty lines and lines starting with `#` are ignored.
+pub fn parse_builtin_objects(list: &str) -> BTreeSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// A reference that is neither part of the device nor a built-in object.
+pub struct MissingReference {
+    pub kind: DependencyKind,
+    pub name: String,
+    /// Where the file was found on the search path, if it was
+    pub found_at: Option<PathBuf>,
+}
+
+/// Compares the files of a device with what its patchers refer to.
+pub struct DependencyReport {
+    /// Sources of the files that nothing refers to
+    pub unreferenced: Vec<String>,
+    pub missing: Vec<MissingReference>,
+}
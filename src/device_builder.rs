@@ -15,66 +15,874 @@
  */
 
 
-use std::{fs};
-use std::path::Path;
-use std::time::SystemTime;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use anyhow::{bail, Context, Result};
 use bytes::{BufMut, Bytes, BytesMut};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde_json::Value;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use unicode_normalization::UnicodeNormalization;
+use zip::ZipArchive;
+use crate::amxd_builder::{build_frozen_amxd, DeviceType, FROZEN_HEADER_LENGTH};
+use crate::amxd_footer::{build_footer, clamp_to_hfsplus_range, hfsplus_epoch, to_hfsplus_time};
+use crate::amxd_reader::{AmxdFile, AmxdPatcher};
+use crate::dependencies::{
+    is_patcher_file, parse_patcher_json, resolve_dependencies, DependencyKind, DependencyReport, KnownFile,
+    MissingReference, SearchPath,
+};
+use crate::error::{BuildError, NameCollision};
 use crate::max_filetypes::determine_file_type;
+use crate::maxpat_project::{
+    create_work_dir, parse_template, preprocess_template_file, read_template,
+};
 
+/// The flag stored in the `flag` footer field of an embedded file.
+#[derive(Debug, Clone, PartialEq)]
 pub enum DeviceFileFlag {
+    /// A regular file
     None,
+    /// A JavaScript file
     JSFile,
-    MainFile
+    /// The main patcher of the device
+    MainFile,
+    /// A value maxbuild doesn't know, kept as it is
+    Other(u32),
 }
 
+/// An entry of the `dlst` footer of a frozen device, describing one embedded file.
+#[derive(Debug, Clone)]
 pub struct DeviceFile {
+    /// Four character code of the file type (`type`)
     pub file_type: String,
+    /// Name of the file, which Max uses to find it on its search path (`fnam`)
     pub file_name: String,
+    /// Size of the file data in bytes (`sz32`)
     pub data_size: u32,
+    /// Offset of the file data, relative to the start of the frozen device header (`of32`)
     pub data_offset: u32,
     pub flag: DeviceFileFlag,
+    /// Stored as an HFS+ timestamp (`mdat`)
     pub modification_date: SystemTime,
+    /// Always 0 for files packed by maxbuild (`vers`)
+    pub version: u32,
 }
 
+/// The packed data region of a frozen device, and the footer entries describing it.
 pub struct DeviceData {
+    /// The data of all files back to back, without the `mx@c` header
     pub data: Bytes,
     pub files: Vec<DeviceFile>
 }
 
-pub fn build_device(main_file_path: &String, paths: &[String]) -> DeviceData {
-    let mut data_buf = BytesMut::new();
-    let mut files: Vec<DeviceFile> = Vec::new();
+/// Where the main patcher of a device comes from.
+pub enum TemplateSource {
+    /// An unfrozen or frozen device, or a plain patcher (`.maxpat` / `.json`) on disk
+    File(String),
+    /// The contents of a template. The name decides whether it is treated as a plain patcher.
+    Memory { name: String, data: Bytes },
+}
+
+impl TemplateSource {
+    fn name(&self) -> &str {
+        match self {
+            TemplateSource::File(path) => path,
+            TemplateSource::Memory { name, .. } => name,
+        }
+    }
+}
+
+/// Builds a frozen device from a template and a set of included files or directories.
+///
+/// ```no_run
+/// use maxbuild::{DeviceBuilder, DeviceType, TemplateSource};
+///
+/// let device = DeviceBuilder::new(TemplateSource::File("MyDevice.amxd".to_string()))
+///     .device_type(DeviceType::AudioEffect)
+///     .include("js")
+///     .exclude("**/*.md")
+///     .build()?;
+///
+/// std::fs::write("MyDevice-frozen.amxd", &device.data)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct DeviceBuilder {
+    template: TemplateSource,
+    device_type: Option<DeviceType>,
+    force_device_type: bool,
+    meta: Option<u32>,
+    includes: Vec<Include>,
+    excludes: Vec<String>,
+    include_hidden: bool,
+    modification_date: ModificationDate,
+    discover_dependencies: bool,
+    search_roots: Vec<String>,
+}
+
+/// Which modification date is stored for the packed files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ModificationDate {
+    /// The modification time of each source file, or of the template for the main patcher
+    #[default]
+    Source,
+    /// The time the build started, for all files
+    BuildTime,
+    /// The same time for all files, e.g. for reproducible builds
+    Fixed(SystemTime),
+}
+
+enum Include {
+    Path { path: String, name: Option<String> },
+    Zip { path: String, sub_path: Option<String> },
+    Data { name: String, source: FileSource },
+}
+
+/// Where the data of an included file comes from.
+#[derive(Clone)]
+pub enum FileSource {
+    Path(String),
+    /// Data that is already loaded, e.g. from a zip archive or stdin.
+    /// The description names the origin of the data in reports and errors.
+    Memory { description: String, data: Bytes },
+}
+
+impl FileSource {
+    /// The path or description of the source, as shown in reports and errors
+    pub fn description(&self) -> &str {
+        match self {
+            FileSource::Path(path) => path,
+            FileSource::Memory { description, .. } => description,
+        }
+    }
+
+    /// The modification time of a file on disk. Data in memory has none.
+    fn modification_date(&self) -> Option<SystemTime> {
+        match self {
+            FileSource::Path(path) => fs::metadata(path).and_then(|metadata| metadata.modified()).ok(),
+            FileSource::Memory { .. } => None,
+        }
+    }
+
+    fn read(&self) -> Result<Bytes, BuildError> {
+        match self {
+            FileSource::Path(path) => fs::read(path).map(Bytes::from).map_err(|e| BuildError::io(path, e)),
+            FileSource::Memory { data, .. } => Ok(data.clone()),
+        }
+    }
+}
+
+/// A file to pack, and the name it gets inside the device.
+pub struct IncludedFile {
+    pub source: FileSource,
+    pub name: String,
+    /// Stored in the footer. If not set, the modification time of the source is used,
+    /// and the date of the main patcher for data in memory.
+    pub modification_date: Option<SystemTime>,
+}
+
+impl IncludedFile {
+    /// A file on disk that keeps its name
+    pub fn new(path: String) -> Self {
+        let name = file_name(&path).to_string();
+        Self { source: FileSource::Path(path), name, modification_date: None }
+    }
+}
+
+/// A file that was packed into a device, and where its data came from.
+pub struct PackedFile {
+    pub source: String,
+    pub file: DeviceFile,
+}
+
+/// What a build did.
+pub struct BuildReport {
+    pub device_type: DeviceType,
+    pub meta: u32,
+    /// All packed files, starting with the main patcher
+    pub files: Vec<PackedFile>,
+    /// Problems that did not stop the build
+    pub warnings: Vec<String>,
+}
+
+/// The result of [`DeviceBuilder::build`].
+pub struct BuiltDevice {
+    /// The complete frozen `.amxd` file
+    pub data: Bytes,
+    pub report: BuildReport,
+}
+
+impl DeviceBuilder {
+    /// A builder without any includes, using the type and `meta` value of the template.
+    pub fn new(template: TemplateSource) -> Self {
+        Self {
+            template,
+            device_type: None,
+            force_device_type: false,
+            meta: None,
+            includes: Vec::new(),
+            excludes: Vec::new(),
+            include_hidden: false,
+            modification_date: ModificationDate::Source,
+            discover_dependencies: false,
+            search_roots: Vec::new(),
+        }
+    }
+
+    /// Sets the device type. Unless forced, it has to match the type of the template.
+    /// Required for plain patcher templates, which don't have a type of their own.
+    pub fn device_type(mut self, device_type: DeviceType) -> Self {
+        self.device_type = Some(device_type);
+        self
+    }
+
+    /// Allows a device type that differs from the type of the template.
+    pub fn force_device_type(mut self, force: bool) -> Self {
+        self.force_device_type = force;
+        self
+    }
+
+    /// Overrides the `meta` header value of the template.
+    pub fn meta(mut self, meta: u32) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+
+    /// Adds a file, or a directory whose files are included recursively.
+    pub fn include(mut self, path: impl Into<String>) -> Self {
+        self.includes.push(Include::Path { path: path.into(), name: None });
+        self
+    }
+
+    /// Adds a single file that is embedded under a different name than it has on disk.
+    pub fn include_as(mut self, path: impl Into<String>, name: impl Into<String>) -> Self {
+        self.includes.push(Include::Path { path: path.into(), name: Some(name.into()) });
+        self
+    }
+
+    /// Adds all files of a zip archive, or only those below the given directory inside of it.
+    /// Like directories, the files are embedded with their plain file names.
+    pub fn include_zip(mut self, path: impl Into<String>, sub_path: Option<String>) -> Self {
+        self.includes.push(Include::Zip { path: path.into(), sub_path });
+        self
+    }
+
+    /// Adds a file from memory. The description names the origin of the data in the build report.
+    pub fn include_data(mut self, name: impl Into<String>, data: impl Into<Bytes>, description: impl Into<String>) -> Self {
+        let source = FileSource::Memory { description: description.into(), data: data.into() };
+        self.includes.push(Include::Data { name: name.into(), source });
+        self
+    }
+
+    /// Chooses the modification date stored for the packed files. By default, the modification
+    /// times of the sources are kept.
+    pub fn modification_date(mut self, modification_date: ModificationDate) -> Self {
+        self.modification_date = modification_date;
+        self
+    }
+
+    /// Also packs the files the template refers to (abstractions, scripts, gen patchers, media and
+    /// pictures), found in the search roots. Patchers found that way are searched as well.
+    pub fn discover_dependencies(mut self, discover_dependencies: bool) -> Self {
+        self.discover_dependencies = discover_dependencies;
+        self
+    }
+
+    /// Adds a directory to look for dependencies in. Without any, the directory of the template is used.
+    pub fn search_root(mut self, path: impl Into<String>) -> Self {
+        self.search_roots.push(path.into());
+        self
+    }
+
+    /// Adds a glob pattern for files to skip, matched against the path relative to the include root.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.excludes.push(pattern.into());
+        self
+    }
+
+    /// Also packs hidden files (starting with `.`) found in include directories, which are skipped by default.
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Builds the frozen device in memory. Failures carry a [`BuildError`] where one applies.
+    pub fn build(&self) -> Result<BuiltDevice> {
+        let work_dir = create_work_dir()?;
+        let result = self.build_in_work_dir(&work_dir);
+        fs::remove_dir_all(&work_dir)?;
+
+        result
+    }
+
+    fn build_in_work_dir(&self, work_dir: &Path) -> Result<BuiltDevice> {
+        let exclude = build_glob_set(&self.excludes)?;
+        let template = self.load_template(self.device_type.as_ref())?;
+        let mut includes = self.collect_includes(&template, &exclude)?;
+
+        let patcher_json = template.patcher_json()
+            .map_err(|e| BuildError::template_parse(self.template.name(), e))?;
+        let mut warnings = Vec::new();
+        if self.discover_dependencies {
+            let discovered_files = self.find_dependencies(&patcher_json, &includes, &exclude, &mut warnings)?;
+            includes.extend(discovered_files);
+        }
+
+        // The main patcher is always embedded as a device
+        let main_file_name = Path::new(file_name(self.template.name())).with_extension("amxd");
+        let main_file = (main_file_name.to_str().unwrap_or_default(), self.template.name());
+        check_name_collisions(
+            [main_file].into_iter().chain(includes.iter().map(|include| (include.name.as_str(), include.source.description())))
+        )?;
+
+        let template_device_type = template.device_type.clone();
+        let device_type = match &self.device_type {
+            None => template_device_type,
+            Some(device_type) if *device_type == template_device_type || self.force_device_type => device_type.clone(),
+            Some(device_type) => bail!(
+                "Device type '{}' does not match the template's device type '{}' (use --force-device-type to override)",
+                device_type,
+                template_device_type
+            ),
+        };
+        let included_names: Vec<_> = includes.iter().map(|include| include.name.clone()).collect();
+        let preprocessed_template = preprocess_template_file(
+            work_dir,
+            self.template.name(),
+            patcher_json,
+            &included_names,
+        )?;
+
+        // The main patcher is read from the work directory, whose modification time is the build time
+        let template_modification_date = match &self.template {
+            TemplateSource::File(path) => FileSource::Path(path.clone()).modification_date(),
+            TemplateSource::Memory { .. } => None,
+        };
+        let main_file = IncludedFile {
+            modification_date: Some(template_modification_date.unwrap_or_else(hfsplus_epoch)),
+            ..IncludedFile::new(preprocessed_template)
+        };
+        let fixed_modification_date = match self.modification_date {
+            ModificationDate::Source => None,
+            ModificationDate::BuildTime => Some(SystemTime::now()),
+            ModificationDate::Fixed(modification_date) => Some(modification_date),
+        };
+        let mut device_data = build_device(&main_file, &includes, fixed_modification_date)?;
+
+        let sources: Vec<_> = [self.template.name().to_string()].into_iter()
+            .chain(includes.into_iter().map(|include| include.source.description().to_string()))
+            .collect();
+        for (file, source) in device_data.files.iter_mut().zip(&sources) {
+            if to_hfsplus_time(&file.modification_date).is_none() {
+                warnings.push(format!(
+                    "The modification date of '{}' lies outside of 1904-2040 and was clamped to that range",
+                    source
+                ));
+                file.modification_date = clamp_to_hfsplus_range(&file.modification_date);
+            }
+        }
+
+        let frozen_device = build_frozen_amxd(
+            &device_type,
+            &template.meta,
+            device_data.data,
+            build_footer(&device_data.files),
+        );
+
+        let files = sources.into_iter().zip(device_data.files)
+            .map(|(source, file)| PackedFile { source, file })
+            .collect();
+
+        Ok(BuiltDevice {
+            data: frozen_device,
+            report: BuildReport { device_type, meta: template.meta, files, warnings },
+        })
+    }
+
+    fn load_template(&self, device_type: Option<&DeviceType>) -> Result<AmxdFile> {
+        match &self.template {
+            TemplateSource::File(path) => read_template(path, device_type, self.meta),
+            TemplateSource::Memory { name, data } => parse_template(name, data.clone(), device_type, self.meta),
+        }
+    }
+
+    /// Collects the included files. Files of a frozen template come first, unless an include
+    /// with the same name replaces them.
+    fn collect_includes(&self, template: &AmxdFile, exclude: &GlobSet) -> Result<Vec<IncludedFile>> {
+        let mut includes: Vec<IncludedFile> = Vec::new();
+        for include in &self.includes {
+            match include {
+                Include::Path { path, name: Some(name) } => includes.push(mapped_file(path, name)?),
+                Include::Path { path, name: None } => {
+                    let filter = IncludeFilter::load(Path::new(path), exclude, self.include_hidden)?;
+                    add_files_recursive(Path::new(path), &filter, &mut includes)?;
+                }
+                Include::Zip { path, sub_path } => {
+                    add_zip_files(path, sub_path.as_deref(), exclude, self.include_hidden, &mut includes)?;
+                }
+                Include::Data { name, source } => {
+                    check_file_name(source.description(), name)?;
+                    includes.push(IncludedFile { source: source.clone(), name: name.clone(), modification_date: None });
+                }
+            }
+        }
+
+        if let AmxdPatcher::Frozen(frozen_template) = &template.patcher {
+            let included_names: Vec<_> = includes.iter().map(|include| collision_key(&include.name)).collect();
+            let mut kept_files = Vec::new();
+            for file in &frozen_template.files {
+                if file.flag == DeviceFileFlag::MainFile || included_names.contains(&collision_key(&file.file_name)) {
+                    continue;
+                }
+
+                let description = format!("{}#{}", self.template.name(), file.file_name);
+                let data = frozen_template.file_data(file)?;
+                kept_files.push(IncludedFile {
+                    source: FileSource::Memory { description, data },
+                    name: file.file_name.clone(),
+                    modification_date: Some(file.modification_date),
+                });
+            }
+
+            includes.splice(0..0, kept_files);
+        }
+
+        Ok(includes)
+    }
+}
+
+impl DeviceBuilder {
+    /// Returns the files the template and the included patchers depend on, which are not included yet.
+    fn find_dependencies(
+        &self,
+        patcher_json: &Value,
+        includes: &[IncludedFile],
+        exclude: &GlobSet,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<IncludedFile>> {
+        let search_path = self.search_path(exclude)?;
+
+        let mut patchers = vec![patcher_json.clone()];
+        for include in includes.iter().filter(|include| is_patcher_file(&include.name)) {
+            patchers.extend(parse_patcher_json(&include.source.read()?));
+        }
+
+        let known_files: Vec<_> = includes.iter()
+            .map(|include| KnownFile { name: include.name.clone(), patcher: None })
+            .collect();
+        let closure = resolve_dependencies(&patchers, &known_files, &search_path);
+        for reference in closure.unresolved.iter().filter(|reference| reference.kind != DependencyKind::Abstraction) {
+            // Unresolved object names are usually built-in objects, other references are missing files
+            warnings.push(format!("Cannot find {} '{}' in the search roots", reference.kind, reference.name));
+        }
+
+        closure.found.into_iter()
+            .map(|(_, path)| Ok(IncludedFile::new(path_to_string(&path)?)))
+            .collect()
+    }
+
+    fn search_path(&self, exclude: &GlobSet) -> Result<SearchPath> {
+        let search_roots = match (&self.template, self.search_roots.is_empty()) {
+            (_, false) => self.search_roots.clone(),
+            (TemplateSource::File(path), true) => {
+                let template_dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty());
+                vec![template_dir.unwrap_or(Path::new(".")).to_string_lossy().to_string()]
+            }
+            (TemplateSource::Memory { .. }, true) => bail!("Finding dependencies of a template in memory requires a search root"),
+        };
+
+        let mut search_path_files = Vec::new();
+        for search_root in &search_roots {
+            let filter = IncludeFilter::load(Path::new(search_root), exclude, self.include_hidden)?;
+            add_files_recursive(Path::new(search_root), &filter, &mut search_path_files)?;
+        }
+
+        Ok(SearchPath::new(search_path_files.into_iter().map(|file| PathBuf::from(file.source.description()))))
+    }
+
+    /// Compares the files that would be packed with the references of the template: lists the files
+    /// nothing refers to, and the references that are neither packed nor one of the built-in objects.
+    pub fn check_dependencies(&self, builtin_objects: &BTreeSet<String>) -> Result<DependencyReport> {
+        let exclude = build_glob_set(&self.excludes)?;
+        // Only the patcher is checked, so a plain patcher template can do without its actual device type
+        let template = self.load_template(Some(self.device_type.as_ref().unwrap_or(&DeviceType::AudioEffect)))?;
+        let includes = self.collect_includes(&template, &exclude)?;
+        let patcher_json = template.patcher_json()
+            .map_err(|e| BuildError::template_parse(self.template.name(), e))?;
+
+        let mut known_files = Vec::new();
+        for include in &includes {
+            let patcher = if is_patcher_file(&include.name) { parse_patcher_json(&include.source.read()?) } else { None };
+            known_files.push(KnownFile { name: include.name.clone(), patcher });
+        }
+        let closure = resolve_dependencies(&[patcher_json], &known_files, &self.search_path(&exclude)?);
 
-    files.push(add_file(main_file_path, DeviceFileFlag::MainFile, &mut data_buf).unwrap());
-    for path in paths {
-        let flag = if path.ends_with(".js") {
-            DeviceFileFlag::JSFile
+        let referenced_names: BTreeSet<_> = closure.known.iter().map(|(_, name)| collision_key(name)).collect();
+        let unreferenced = includes.iter()
+            .filter(|include| !referenced_names.contains(&collision_key(&include.name)))
+            .map(|include| include.source.description().to_string())
+            .collect();
+
+        let mut missing: BTreeMap<(DependencyKind, String), Option<PathBuf>> = BTreeMap::new();
+        for reference in closure.unresolved {
+            if reference.kind != DependencyKind::Abstraction || !builtin_objects.contains(&reference.name) {
+                missing.entry((reference.kind, reference.name)).or_default();
+            }
+        }
+        if !self.discover_dependencies {
+            // Otherwise these would be packed along with the includes
+            for (reference, path) in closure.found {
+                missing.insert((reference.kind, reference.name), Some(path));
+            }
+        }
+
+        Ok(DependencyReport {
+            unreferenced,
+            missing: missing.into_iter()
+                .map(|((kind, name), found_at)| MissingReference { kind, name, found_at })
+                .collect(),
+        })
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|file_name| file_name.to_str()).unwrap_or(path)
+}
+
+/// Live mostly runs on macOS, whose file systems don't distinguish names by case or
+/// Unicode normalization form. Names that only differ in those ways map to the same key.
+pub fn collision_key(file_name: &str) -> String {
+    file_name.nfc().collect::<String>().to_lowercase()
+}
+
+/// Fails if several of the given files, as pairs of embedded name and source, would end up
+/// with names Max can't tell apart. All collisions are reported at once.
+pub fn check_name_collisions<'a>(files: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<(), BuildError> {
+    let mut files_by_key: BTreeMap<String, Vec<(&str, &str)>> = BTreeMap::new();
+    for (name, source) in files {
+        files_by_key.entry(collision_key(name)).or_default().push((name, source));
+    }
+
+    let collisions: Vec<_> = files_by_key.into_values()
+        .filter(|files| files.len() > 1)
+        .map(|files| NameCollision {
+            name: files[0].0.to_string(),
+            sources: files.iter().map(|(_, source)| source.to_string()).collect(),
+        })
+        .collect();
+
+    if !collisions.is_empty() {
+        return Err(BuildError::DuplicateNames { collisions });
+    }
+
+    Ok(())
+}
+
+/// Compiles the exclude patterns, which are matched against paths relative to an include root.
+pub fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("Invalid exclude pattern '{}'", pattern))?);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Name of the file in an include directory that lists files to leave out, in gitignore syntax
+pub const IGNORE_FILE_NAME: &str = ".maxbuildignore";
+
+/// Decides which files below an include root are packed.
+struct IncludeFilter<'a> {
+    root: &'a Path,
+    exclude: &'a GlobSet,
+    ignore: Gitignore,
+    include_hidden: bool,
+}
+
+impl<'a> IncludeFilter<'a> {
+    fn load(root: &'a Path, exclude: &'a GlobSet, include_hidden: bool) -> Result<Self> {
+        let ignore_file = root.join(IGNORE_FILE_NAME);
+        let ignore = if ignore_file.is_file() {
+            let mut builder = GitignoreBuilder::new(root);
+            if let Some(e) = builder.add(&ignore_file) {
+                return Err(e).with_context(|| format!("Cannot read '{}'", ignore_file.display()));
+            }
+            builder.build().with_context(|| format!("Cannot read '{}'", ignore_file.display()))?
         } else {
-            DeviceFileFlag::None
+            Gitignore::empty()
         };
 
-        files.push(add_file(path, flag, &mut data_buf).unwrap());
+        Ok(Self { root, exclude, ignore, include_hidden })
     }
 
-    DeviceData { data: data_buf.freeze(), files, }
+    /// Exclude patterns are matched against the path relative to the include root,
+    /// or against the file name for files that were included directly.
+    fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let relative_path = match path.strip_prefix(self.root) {
+            Ok(relative_path) if !relative_path.as_os_str().is_empty() => relative_path,
+            _ => match path.file_name() {
+                Some(file_name) => Path::new(file_name),
+                None => return false,
+            },
+        };
+
+        if self.exclude.is_match(relative_path) {
+            return true;
+        }
+        if path == self.root {
+            // Hidden files and the ignore file only apply to the contents of an include directory
+            return false;
+        }
+
+        let file_name = path.file_name().unwrap_or_default();
+        file_name == IGNORE_FILE_NAME
+            || (!self.include_hidden && file_name.to_string_lossy().starts_with('.'))
+            || self.ignore.matched(path, is_dir).is_ignore()
+    }
 }
 
-fn add_file(file_path: &String, flag: DeviceFileFlag, data_buf: &mut BytesMut) -> Option<DeviceFile> {
-    let data_offset = data_buf.len();
-    let path = Path::new(file_path);
+fn check_file_name(source: &str, name: &str) -> Result<(), BuildError> {
+    if name.is_empty() || Path::new(name).file_name() != Some(name.as_ref()) {
+        return Err(BuildError::UnsupportedFile {
+            path: PathBuf::from(source),
+            reason: format!("'{}' is not a valid file name", name),
+        });
+    }
+
+    Ok(())
+}
+
+/// An include that is renamed has to be a single file with a plain file name.
+fn mapped_file(path: &str, name: &str) -> Result<IncludedFile, BuildError> {
+    check_file_name(path, name)?;
+    if !fs::metadata(path).map_err(|e| BuildError::io(path, e))?.is_file() {
+        return Err(BuildError::UnsupportedFile {
+            path: PathBuf::from(path),
+            reason: "only single files can be renamed".to_string(),
+        });
+    }
+
+    Ok(IncludedFile { source: FileSource::Path(path.to_string()), name: name.to_string(), modification_date: None })
+}
+
+/// Loads the files of a zip archive into memory. Exclude patterns are matched against the path
+/// relative to the sub path, hidden files are skipped the same way as in directories.
+fn add_zip_files(
+    path: &str,
+    sub_path: Option<&str>,
+    exclude: &GlobSet,
+    include_hidden: bool,
+    includes: &mut Vec<IncludedFile>,
+) -> Result<()> {
+    let file = fs::File::open(path).map_err(|e| BuildError::io(path, e))?;
+    let mut archive = ZipArchive::new(file).with_context(|| format!("Cannot open zip archive '{}'", path))?;
+    let root = Path::new(sub_path.unwrap_or_default().trim_matches('/'));
 
-    let bytes = fs::read(path).unwrap();
-    let length = bytes.len();
+    let mut found_sub_path = sub_path.is_none();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).with_context(|| format!("Cannot read zip archive '{}'", path))?;
+        let Some(entry_path) = entry.enclosed_name() else {
+            let name = entry.name().map(|name| name.to_string()).unwrap_or_default();
+            bail!("Zip archive '{}' contains the invalid path '{}'", path, name);
+        };
+        let Ok(relative_path) = entry_path.strip_prefix(root) else {
+            continue;
+        };
+        found_sub_path = true;
+
+        let is_hidden = relative_path.components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+        if entry.is_dir() || (is_hidden && !include_hidden) || exclude.is_match(relative_path) {
+            continue;
+        }
+
+        let description = format!("{}#{}", path, entry_path.display());
+        let name = entry_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let modification_date = entry.last_modified().and_then(zip_time_to_system_time);
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data).with_context(|| format!("Cannot read '{}'", description))?;
+
+        let source = FileSource::Memory { description, data: Bytes::from(data) };
+        includes.push(IncludedFile { source, name, modification_date });
+    }
 
-    println!("Packing file: {}", file_path);
-    data_buf.put(Bytes::from(bytes));
+    if !found_sub_path {
+        bail!("Zip archive '{}' does not contain '{}'", path, root.display());
+    }
+
+    Ok(())
+}
+
+fn add_files_recursive(path: &Path, filter: &IncludeFilter, includes: &mut Vec<IncludedFile>) -> Result<(), BuildError> {
+    let metadata = fs::metadata(path).map_err(|e| BuildError::io(path, e))?;
+    if filter.is_excluded(path, metadata.is_dir()) {
+        return Ok(());
+    }
+
+    if metadata.is_file() {
+        includes.push(IncludedFile::new(path_to_string(path)?));
+        return Ok(());
+    }
+
+    // read_dir returns entries in file system order, which differs between machines
+    let mut dir = fs::read_dir(path)
+        .and_then(|entries| entries.map(|res| res.map(|e| e.path())).collect::<Result<Vec<PathBuf>, io::Error>>())
+        .map_err(|e| BuildError::io(path, e))?;
+    dir.sort();
+
+    for entry in dir {
+        add_files_recursive(&entry, filter, includes)?;
+    }
+
+    Ok(())
+}
+
+fn path_to_string(path: &Path) -> Result<String, BuildError> {
+    path.to_str().map(str::to_string).ok_or_else(|| BuildError::UnsupportedFile {
+        path: path.to_path_buf(),
+        reason: "the path is not valid UTF-8".to_string(),
+    })
+}
+
+/// Zip archives store local time without a time zone, which is treated as UTC here.
+fn zip_time_to_system_time(time: zip::DateTime) -> Option<SystemTime> {
+    utc_time(time.year() as i64, time.month() as u32, time.day() as u32, time.hour() as u32, time.minute() as u32, time.second() as u32)
+}
+
+/// Converts a UTC date and time of the proleptic Gregorian calendar. Returns `None` for dates
+/// that don't exist, like February 30th.
+pub fn utc_time(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> Option<SystemTime> {
+    if !(1..=12).contains(&month) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if is_leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    // Days since 1970-01-01
+    let (month, day) = (month as i64, day as i64);
+    let shifted_year = if month <= 2 { year.checked_sub(1)? } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // Years far outside of what SystemTime can hold overflow here
+    let days = era.checked_mul(146097)?.checked_add(day_of_era - 719468)?;
+
+    let seconds = days.checked_mul(86400)?.checked_add(hour as i64 * 3600 + minute as i64 * 60 + second as i64)?;
+    match u64::try_from(seconds) {
+        Ok(seconds) => SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(seconds)),
+        Err(_) => SystemTime::UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs())),
+    }
+}
+
+/// Packs the main patcher and the included files. Unless a fixed modification date is given,
+/// each file keeps the modification date of its source. Data in memory has none, so it gets
+/// the date of the main patcher, which keeps builds reproducible.
+pub fn build_device(
+    main_file: &IncludedFile,
+    included_files: &[IncludedFile],
+    fixed_modification_date: Option<SystemTime>,
+) -> Result<DeviceData, BuildError> {
+    let mut data_buf = BytesMut::new();
+    let mut files: Vec<DeviceFile> = Vec::new();
+
+    let fallback_modification_date = main_file.modification_date
+        .or_else(|| main_file.source.modification_date())
+        .unwrap_or_else(hfsplus_epoch);
+    files.push(add_file(main_file, DeviceFileFlag::MainFile, fixed_modification_date, fallback_modification_date, &mut data_buf)?);
+    for included_file in included_files {
+        let flag = file_flag(&included_file.name);
+        files.push(add_file(included_file, flag, fixed_modification_date, fallback_modification_date, &mut data_buf)?);
+    }
+
+    Ok(DeviceData { data: data_buf.freeze(), files, })
+}
+
+/// Packs already loaded files, in the given order, recomputing all data offsets and sizes.
+pub fn rebuild_device(entries: Vec<(DeviceFile, Bytes)>) -> Result<DeviceData, BuildError> {
+    let mut data_buf = BytesMut::new();
+    let mut files: Vec<DeviceFile> = Vec::new();
+
+    for (mut file, bytes) in entries {
+        file.data_offset = checked_data_offset(data_buf.len(), bytes.len(), &file.file_name)?;
+        file.data_size = bytes.len() as u32;
+        data_buf.put(bytes);
+        files.push(file);
+    }
+
+    Ok(DeviceData { data: data_buf.freeze(), files, })
+}
+
+/// The flag Max uses for a file of the given name: scripts are flagged as JavaScript.
+pub fn file_flag(path: &str) -> DeviceFileFlag {
+    if path.ends_with(".js") {
+        DeviceFileFlag::JSFile
+    } else {
+        DeviceFileFlag::None
+    }
+}
+
+/// Describes a file that is about to be packed, named and typed after the last part of its path.
+/// The offset is filled in once its data is placed, and the date is 1904-01-01 until one is set.
+/// Returns `None` if the file has no extension.
+pub fn new_device_file(file_path: &str, flag: DeviceFileFlag, data_size: usize) -> Option<DeviceFile> {
+    let path = Path::new(file_path);
 
     Some(DeviceFile {
         file_type: determine_file_type(path.extension()?.to_str()?).four_character_code,
         file_name: path.file_name()?.to_str()?.to_owned(),
-        data_size: length as u32,
-        data_offset: data_offset as u32 + 16, // +16 to account for frozen device header
+        data_size: data_size as u32,
+        data_offset: 0,
         flag,
-        modification_date: SystemTime::now()
+        modification_date: hfsplus_epoch(),
+        version: 0,
     })
+}
+
+fn add_file(
+    included_file: &IncludedFile,
+    flag: DeviceFileFlag,
+    fixed_modification_date: Option<SystemTime>,
+    fallback_modification_date: SystemTime,
+    data_buf: &mut BytesMut,
+) -> Result<DeviceFile, BuildError> {
+    let data_offset = data_buf.len();
+    let file_path = included_file.source.description();
+
+    let bytes = included_file.source.read()?;
+    let mut file = new_device_file(&included_file.name, flag, bytes.len()).ok_or_else(|| BuildError::UnsupportedFile {
+        path: PathBuf::from(file_path),
+        reason: "the file has no extension to determine its type from".to_string(),
+    })?;
+
+    file.data_offset = checked_data_offset(data_offset, bytes.len(), file_path)?;
+    file.modification_date = fixed_modification_date
+        .or(included_file.modification_date)
+        .or_else(|| included_file.source.modification_date())
+        .unwrap_or(fallback_modification_date);
+
+    data_buf.put(bytes);
+
+    Ok(file)
+}
+
+/// Returns the footer offset of a file placed at the given position of the data buffer,
+/// failing if its end (and with it the footer) would not be addressable with 32 bits.
+fn checked_data_offset(data_offset: usize, data_size: usize, path: &str) -> Result<u32, BuildError> {
+    let data_end = data_offset as u64 + data_size as u64 + FROZEN_HEADER_LENGTH as u64;
+    if data_end > u32::MAX as u64 {
+        return Err(BuildError::SizeOverflow { path: PathBuf::from(path) });
+    }
+
+    Ok((data_offset + FROZEN_HEADER_LENGTH) as u32)
 }
\ No newline at end of file
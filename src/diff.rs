@@ -0,0 +1,238 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_reader::{read_amxd, AmxdFile, AmxdPatcher};
+use maxbuild::device_builder::DeviceFileFlag;
+use maxbuild::maxpat_project::parse_patcher_data;
+use anyhow::Result;
+use bytes::Bytes;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+pub fn diff_devices(old_path: &str, new_path: &str) -> Result<()> {
+    let old_device = read_amxd(old_path)?;
+    let new_device = read_amxd(new_path)?;
+
+    let sections = [
+        ("Header", diff_headers(&old_device, &new_device)),
+        ("Embedded files", diff_embedded_files(&old_device, &new_device)?),
+        ("Main patcher", diff_patchers(&old_device.patcher_json()?, &new_device.patcher_json()?)),
+    ];
+
+    let mut has_differences = false;
+    for (title, differences) in sections {
+        if differences.is_empty() {
+            continue;
+        }
+
+        has_differences = true;
+        println!("{}:", title);
+        for difference in differences {
+            println!("  {}", difference);
+        }
+    }
+
+    if !has_differences {
+        println!("No differences");
+    }
+
+    Ok(())
+}
+
+fn diff_headers(old_device: &AmxdFile, new_device: &AmxdFile) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    if old_device.device_type != new_device.device_type {
+        differences.push(format!(
+            "~ device type: {} -> {}",
+            old_device.device_type,
+            new_device.device_type
+        ));
+    }
+    if old_device.meta != new_device.meta {
+        differences.push(format!("~ meta: {} -> {}", old_device.meta, new_device.meta));
+    }
+
+    let old_frozen = matches!(old_device.patcher, AmxdPatcher::Frozen(_));
+    let new_frozen = matches!(new_device.patcher, AmxdPatcher::Frozen(_));
+    if old_frozen != new_frozen {
+        differences.push(format!("~ frozen: {} -> {}", old_frozen, new_frozen));
+    }
+
+    differences
+}
+
+fn diff_embedded_files(old_device: &AmxdFile, new_device: &AmxdFile) -> Result<Vec<String>> {
+    let old_files = embedded_files(old_device)?;
+    let new_files = embedded_files(new_device)?;
+    let mut differences = Vec::new();
+
+    for (name, old_data) in &old_files {
+        match new_files.get(name) {
+            None => differences.push(format!("- {} ({} bytes)", name, old_data.len())),
+            Some(new_data) if new_data != old_data => {
+                differences.push(format!("~ {} ({} -> {} bytes)", name, old_data.len(), new_data.len()));
+
+                if let (Some(old_patcher), Some(new_patcher)) = (as_patcher(old_data), as_patcher(new_data)) {
+                    for difference in diff_patchers(&old_patcher, &new_patcher) {
+                        differences.push(format!("    {}", difference));
+                    }
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, new_data) in &new_files {
+        if !old_files.contains_key(name) {
+            differences.push(format!("+ {} ({} bytes)", name, new_data.len()));
+        }
+    }
+
+    Ok(differences)
+}
+
+/// Collects all embedded files except the main patcher, which is compared structurally.
+fn embedded_files(device: &AmxdFile) -> Result<BTreeMap<String, Bytes>> {
+    let mut files = BTreeMap::new();
+
+    if let AmxdPatcher::Frozen(frozen_device) = &device.patcher {
+        for file in &frozen_device.files {
+            if file.flag != DeviceFileFlag::MainFile {
+                files.insert(file.file_name.clone(), frozen_device.file_data(file)?);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn as_patcher(data: &[u8]) -> Option<Value> {
+    parse_patcher_data(data).ok()
+        .filter(|json| json["patcher"].is_object())
+}
+
+fn diff_patchers(old_json: &Value, new_json: &Value) -> Vec<String> {
+    let mut differences = Vec::new();
+    diff_patcher(&old_json["patcher"], &new_json["patcher"], "", &mut differences);
+
+    differences
+}
+
+fn diff_patcher(old_patcher: &Value, new_patcher: &Value, prefix: &str, differences: &mut Vec<String>) {
+    let empty = Map::new();
+    let old_attributes = old_patcher.as_object().unwrap_or(&empty);
+    let new_attributes = new_patcher.as_object().unwrap_or(&empty);
+
+    diff_attributes(old_attributes, new_attributes, &["boxes", "lines"], &format!("{}patcher", prefix), differences);
+
+    let old_boxes = boxes_by_id(old_patcher);
+    let new_boxes = boxes_by_id(new_patcher);
+    for (id, old_box) in &old_boxes {
+        match new_boxes.get(id) {
+            None => differences.push(format!("- {}box {} {}", prefix, id, describe_box(old_box))),
+            Some(new_box) => {
+                let old_box_attributes = old_box.as_object().unwrap_or(&empty);
+                let new_box_attributes = new_box.as_object().unwrap_or(&empty);
+                let box_prefix = format!("{}box {}", prefix, id);
+
+                diff_attributes(old_box_attributes, new_box_attributes, &["patcher"], &box_prefix, differences);
+
+                if old_box["patcher"].is_object() || new_box["patcher"].is_object() {
+                    let subpatcher_prefix = format!("{} > ", box_prefix);
+                    diff_patcher(&old_box["patcher"], &new_box["patcher"], &subpatcher_prefix, differences);
+                }
+            }
+        }
+    }
+    for (id, new_box) in &new_boxes {
+        if !old_boxes.contains_key(id) {
+            differences.push(format!("+ {}box {} {}", prefix, id, describe_box(new_box)));
+        }
+    }
+
+    let old_lines = lines(old_patcher);
+    let new_lines = lines(new_patcher);
+    for line in old_lines.iter().filter(|line| !new_lines.contains(line)) {
+        differences.push(format!("- {}line {}", prefix, line));
+    }
+    for line in new_lines.iter().filter(|line| !old_lines.contains(line)) {
+        differences.push(format!("+ {}line {}", prefix, line));
+    }
+}
+
+fn diff_attributes(
+    old_attributes: &Map<String, Value>,
+    new_attributes: &Map<String, Value>,
+    ignored_keys: &[&str],
+    prefix: &str,
+    differences: &mut Vec<String>,
+) {
+    for (key, old_value) in old_attributes {
+        if ignored_keys.contains(&key.as_str()) {
+            continue;
+        }
+
+        match (old_value, new_attributes.get(key)) {
+            (_, None) => differences.push(format!("- {} {}: {}", prefix, key, old_value)),
+            (Value::Object(old_object), Some(Value::Object(new_object))) => {
+                // Nested dictionaries are compared key by key to keep the output readable
+                let nested_prefix = format!("{} {}", prefix, key);
+                diff_attributes(old_object, new_object, &[], &nested_prefix, differences);
+            }
+            (_, Some(new_value)) if new_value != old_value => {
+                differences.push(format!("~ {} {}: {} -> {}", prefix, key, old_value, new_value))
+            }
+            _ => {}
+        }
+    }
+    for (key, new_value) in new_attributes {
+        if !ignored_keys.contains(&key.as_str()) && !old_attributes.contains_key(key) {
+            differences.push(format!("+ {} {}: {}", prefix, key, new_value));
+        }
+    }
+}
+
+fn boxes_by_id(patcher: &Value) -> BTreeMap<String, &Value> {
+    patcher["boxes"].as_array()
+        .into_iter()
+        .flatten()
+        .map(|entry| &entry["box"])
+        .map(|box_json| (box_json["id"].as_str().unwrap_or_default().to_string(), box_json))
+        .collect()
+}
+
+fn describe_box(box_json: &Value) -> String {
+    let maxclass = box_json["maxclass"].as_str().unwrap_or_default();
+    match box_json["text"].as_str() {
+        Some(text) => format!("({} \"{}\")", maxclass, text),
+        None => format!("({})", maxclass),
+    }
+}
+
+fn lines(patcher: &Value) -> Vec<String> {
+    patcher["lines"].as_array()
+        .into_iter()
+        .flatten()
+        .map(|entry| &entry["patchline"])
+        .map(|line| format!("{} -> {}", describe_endpoint(&line["source"]), describe_endpoint(&line["destination"])))
+        .collect()
+}
+
+fn describe_endpoint(endpoint: &Value) -> String {
+    format!("{}:{}", endpoint[0].as_str().unwrap_or_default(), endpoint[1])
+}
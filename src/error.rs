@@ -0,0 +1,106 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::{error, fmt, io};
+
+/// Files whose names Max can't tell apart.
+#[derive(Debug)]
+pub struct NameCollision {
+    pub name: String,
+    pub sources: Vec<String>,
+}
+
+/// The errors a build can fail with. Each kind has its own process exit code,
+/// so scripts can tell them apart.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The template is not a valid device or patcher
+    TemplateParse { path: String, message: String },
+    /// A template or include does not exist
+    MissingFile { path: PathBuf },
+    /// A file cannot be packed into a device
+    UnsupportedFile { path: PathBuf, reason: String },
+    /// Several files would be embedded under the same name
+    DuplicateNames { collisions: Vec<NameCollision> },
+    /// The device would be larger than the 4 GiB the footer offsets can address
+    SizeOverflow { path: PathBuf },
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl BuildError {
+    /// Wraps an I/O error, reporting files that don't exist as [`BuildError::MissingFile`].
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => BuildError::MissingFile { path },
+            _ => BuildError::Io { path, source },
+        }
+    }
+
+    pub fn template_parse(path: &str, error: anyhow::Error) -> Self {
+        BuildError::TemplateParse { path: path.to_string(), message: format!("{:#}", error) }
+    }
+
+    /// 1 is used for all other errors, 2 for invalid command line arguments.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BuildError::TemplateParse { .. } => 3,
+            BuildError::MissingFile { .. } => 4,
+            BuildError::UnsupportedFile { .. } => 5,
+            BuildError::DuplicateNames { .. } => 6,
+            BuildError::SizeOverflow { .. } => 7,
+            BuildError::Io { .. } => 8,
+        }
+    }
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::TemplateParse { path, message } => {
+                write!(f, "Cannot parse template '{}': {}", path, message)
+            }
+            BuildError::MissingFile { path } => write!(f, "File '{}' does not exist", path.display()),
+            BuildError::UnsupportedFile { path, reason } => {
+                write!(f, "Cannot pack '{}': {}", path.display(), reason)
+            }
+            BuildError::DuplicateNames { collisions } => {
+                let collisions: Vec<_> = collisions.iter()
+                    .map(|collision| format!("'{}' ({})", collision.name, collision.sources.join(", ")))
+                    .collect();
+                write!(f, "Several files would be embedded under the same name: {}", collisions.join("; "))
+            }
+            BuildError::SizeOverflow { path } => write!(
+                f,
+                "Device exceeds the 4 GiB limit of the .amxd format while packing '{}'",
+                path.display()
+            ),
+            BuildError::Io { path, .. } => write!(f, "Cannot access '{}'", path.display()),
+        }
+    }
+}
+
+impl error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BuildError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
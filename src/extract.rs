@@ -0,0 +1,56 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_reader::{read_amxd, AmxdPatcher};
+use crate::args::ExtractArgs;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::io::{self, Write};
+
+pub fn extract_file(args: &ExtractArgs) -> Result<()> {
+    let device = read_amxd(&args.device)?;
+    let frozen_device = match &device.patcher {
+        AmxdPatcher::Frozen(frozen_device) => frozen_device,
+        AmxdPatcher::Unfrozen(_) => bail!("'{}' is not a frozen device", args.device),
+    };
+
+    let file = if args.main {
+        frozen_device.main_file()
+            .ok_or_else(|| anyhow!("Device does not contain a main patcher"))?
+    } else {
+        let name = args.name.as_deref().ok_or_else(|| anyhow!("No file name given"))?;
+        frozen_device.files.iter()
+            .find(|file| file.file_name == name)
+            .ok_or_else(|| anyhow!("Device does not contain a file named '{}'", name))?
+    };
+    let data = frozen_device.file_data(file)?;
+
+    match &args.output {
+        None => io::stdout().write_all(&data)?,
+        Some(output) => {
+            fs::write(output, &data).with_context(|| format!("Cannot write file '{}'", output))?;
+            fs::File::options()
+                .write(true)
+                .open(output)?
+                .set_modified(file.modification_date)
+                .with_context(|| format!("Cannot set the modification time of '{}'", output))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_footer::to_hfsplus_time_clamped;
+use maxbuild::amxd_reader::{read_amxd, AmxdFile, AmxdPatcher};
+use maxbuild::device_builder::DeviceFile;
+use anyhow::Result;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct DeviceReport {
+    device_type: String,
+    ampf: String,
+    meta: u32,
+    frozen: bool,
+    patcher_size: usize,
+    files: Vec<FileEntryReport>,
+}
+
+#[derive(Serialize)]
+struct FileEntryReport {
+    #[serde(rename = "type")]
+    file_type: String,
+    fnam: String,
+    sz32: u32,
+    of32: u32,
+    flag: u32,
+    mdat: u32,
+    vers: u32,
+}
+
+impl FileEntryReport {
+    fn new(file: &DeviceFile) -> Self {
+        Self {
+            file_type: file.file_type.clone(),
+            fnam: file.file_name.clone(),
+            sz32: file.data_size,
+            of32: file.data_offset,
+            flag: file.flag.to_raw(),
+            mdat: to_hfsplus_time_clamped(&file.modification_date),
+            vers: file.version,
+        }
+    }
+}
+
+pub fn inspect_device(device_path: &str, json: bool) -> Result<()> {
+    let report = build_report(&read_amxd(device_path)?);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
+
+fn build_report(device: &AmxdFile) -> DeviceReport {
+    let (frozen, patcher_size, files) = match &device.patcher {
+        AmxdPatcher::Unfrozen(patcher) => (false, patcher.len(), Vec::new()),
+        AmxdPatcher::Frozen(frozen_device) => (
+            true,
+            frozen_device.body.len(),
+            frozen_device.files.iter().map(FileEntryReport::new).collect(),
+        ),
+    };
+
+    DeviceReport {
+        device_type: device.device_type.to_string(),
+        ampf: String::from_utf8_lossy(&device.device_type.to_header_representation()).to_string(),
+        meta: device.meta,
+        frozen,
+        patcher_size,
+        files,
+    }
+}
+
+fn print_report(report: &DeviceReport) {
+    println!("Device type:  {} ({})", report.device_type, report.ampf);
+    println!("Meta:         {}", report.meta);
+    println!("Frozen:       {}", if report.frozen { "yes" } else { "no" });
+    println!("Patcher size: {} bytes", report.patcher_size);
+
+    if !report.frozen {
+        return;
+    }
+
+    let name_width = report.files.iter()
+        .map(|file| file.fnam.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("fnam".len());
+
+    println!();
+    println!(
+        "{:<4}  {:<name_width$}  {:>10}  {:>10}  {:>4}  {:>10}  {:>4}",
+        "type", "fnam", "sz32", "of32", "flag", "mdat", "vers"
+    );
+    for file in &report.files {
+        println!(
+            "{:<4}  {:<name_width$}  {:>10}  {:>10}  {:>4}  {:>10}  {:>4}",
+            file.file_type, file.fnam, file.sz32, file.of32, file.flag, file.mdat, file.vers
+        );
+    }
+}
@@ -0,0 +1,39 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+//! Assembles frozen Max for Live devices (`.amxd`) from an unfrozen device or patcher
+//! and additional files, and reads them back.
+//!
+//! The main entry point for building is [`DeviceBuilder`], reading is done with
+//! [`amxd_reader::read_amxd`].
+
+pub mod amxd_builder;
+pub mod amxd_fields;
+pub mod amxd_footer;
+pub mod amxd_reader;
+pub mod dependencies;
+pub mod device_builder;
+pub mod error;
+pub mod maxpat_project;
+pub mod max_filetypes;
+
+pub use amxd_builder::DeviceType;
+pub use device_builder::{
+    BuildReport, BuiltDevice, DeviceBuilder, DeviceFile, DeviceFileFlag, FileSource, IncludedFile, ModificationDate, PackedFile, TemplateSource,
+};
+pub use error::{BuildError, NameCollision};
+pub use max_filetypes::{determine_file_type, MaxFileType, ProjectContentSection};
@@ -15,71 +15,185 @@
  */
 
 
-use crate::amxd_builder::{build_frozen_amxd};
-use crate::amxd_footer::build_footer;
-use crate::args::MaxBuildArgs;
-use crate::device_builder::build_device;
+use crate::args::{MaxBuildArgs, MaxBuildCommand};
+use anyhow::{Context, Result};
 use clap::Parser;
+use maxbuild::{BuildError, DeviceBuilder, ModificationDate, TemplateSource};
+use std::fs;
+use std::io::Read;
 use std::path::Path;
-use std::{fs, io};
 use std::process::exit;
-use bytes::{Buf, Bytes};
-use crate::maxpat_project::preprocess_template_file;
+use std::time::{Duration, SystemTime};
+use crate::project_config::{missing_setting, resolve_build_settings, split_include, split_zip_include, BuildSettings};
+use crate::check_deps::check_dependencies;
+use crate::diff::diff_devices;
+use crate::extract::extract_file;
+use crate::inspect::inspect_device;
+use crate::repair::repair_device;
+use crate::textconv::print_textconv;
+use crate::unfreeze::unfreeze_device;
+use crate::update::update_device;
+use crate::verify::verify_device;
 
-mod amxd_builder;
-mod amxd_fields;
-mod amxd_footer;
 mod args;
-mod device_builder;
-mod maxpat_project;
-mod max_filetypes;
+mod check_deps;
+mod diff;
+mod extract;
+mod inspect;
+mod project_config;
+mod repair;
+mod textconv;
+mod unfreeze;
+mod update;
+mod verify;
 
 fn main() {
     let args = MaxBuildArgs::parse();
 
-    let mut includes: Vec<String> = Vec::new();
-    for included_dir in args.include {
-        add_files_recursive(&included_dir, &mut includes).unwrap();
-    }
-
-    let meta = Bytes::from(fs::read(&args.template).unwrap()[20..24].to_vec()).get_u32_le();
-    let preprocessed_template = match preprocess_template_file(&args.template, &includes) {
-        Ok(x) => x,
-        Err(e) => {
-            eprintln!("Cannot process template file:{}\n{}", e, e.backtrace());
-            exit(1);
-        }
+    let result = match args.command {
+        Some(MaxBuildCommand::Unfreeze(args)) => unfreeze_device(&args.device, &args.output_dir),
+        Some(MaxBuildCommand::Inspect(args)) => inspect_device(&args.device, args.json),
+        Some(MaxBuildCommand::Diff(args)) => diff_devices(&args.old_device, &args.new_device),
+        Some(MaxBuildCommand::Textconv(args)) => print_textconv(&args.device),
+        Some(MaxBuildCommand::Update(args)) => update_device(&args),
+        Some(MaxBuildCommand::Extract(args)) => extract_file(&args),
+        Some(MaxBuildCommand::Verify(args)) => verify_device(&args.device),
+        Some(MaxBuildCommand::Repair(args)) => repair_device(&args),
+        Some(MaxBuildCommand::CheckDeps(args)) => check_dependencies(args),
+        None => resolve_build_settings(args.build.unwrap_or_default()).and_then(build_all),
     };
 
-    let device_data = build_device(&preprocessed_template, &includes);
-    let frozen_device = build_frozen_amxd(
-        &args.device_type,
-        &meta,
-        device_data.data,
-        build_footer(&device_data.files),
-    );
+    if let Err(e) = result {
+        eprintln!("Error: {:#}", e);
+        exit(exit_code(&e));
+    }
+}
 
-    fs::write(Path::new(&args.output_file), &frozen_device).unwrap();
-    fs::remove_dir_all(Path::new(&preprocessed_template).parent().unwrap()).unwrap();
+/// Build errors have their own exit codes, everything else exits with 1.
+fn exit_code(error: &anyhow::Error) -> i32 {
+    error.chain()
+        .find_map(|cause| cause.downcast_ref::<BuildError>())
+        .map_or(1, BuildError::exit_code)
 }
 
-fn add_files_recursive(path: &str, includes: &mut Vec<String>) -> io::Result<()> {
-    if fs::metadata(path)?.is_file() {
-        includes.push(path.to_string());
-        return Ok(());
+struct BuildSummary {
+    file_count: usize,
+    size: usize,
+}
+
+fn build_all(targets: Vec<BuildSettings>) -> Result<()> {
+    if let [target] = targets.as_slice() {
+        return build(target).map(|_| ());
     }
 
-    let dir = fs::read_dir(path)?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, io::Error>>()?;
+    let mut results = Vec::new();
+    for target in &targets {
+        println!("Building device: {}", target.name);
+        results.push(build(target));
+    }
 
-    for entry in dir {
-        if entry.is_dir() {
-            add_files_recursive(entry.as_path().to_str().unwrap(), includes)?
-        } else {
-            includes.push(entry.as_path().to_str().unwrap().to_string());
+    println!();
+    println!("Summary:");
+    let mut failed = 0;
+    let mut first_error = None;
+    for (target, result) in targets.iter().zip(results) {
+        match result {
+            Ok(summary) => println!(
+                "  ok      {} -> {} ({} files, {} bytes)",
+                target.name, target.output_file.as_deref().unwrap_or_default(), summary.file_count, summary.size
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("  failed  {}: {:#}", target.name, e);
+                first_error.get_or_insert(e);
+            }
         }
     }
 
+    if let Some(e) = first_error {
+        // Keeps the first error in the chain, so its exit code is used
+        return Err(e.context(format!("{} of {} devices failed to build", failed, targets.len())));
+    }
+
     Ok(())
 }
+
+fn build(args: &BuildSettings) -> Result<BuildSummary> {
+    let output_file = args.output_file.as_ref().ok_or_else(|| missing_setting("--output-file", "output"))?;
+    let device = create_builder(args)?.build()?;
+    for packed_file in &device.report.files {
+        if packed_file.source.ends_with(&packed_file.file.file_name) {
+            println!("Packing file: {}", packed_file.source);
+        } else {
+            println!("Packing file: {} as {}", packed_file.source, packed_file.file.file_name);
+        }
+    }
+    for warning in &device.report.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    let output_path = Path::new(output_file);
+    if let Some(output_dir) = output_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(output_dir).map_err(|e| BuildError::io(output_dir, e))?;
+    }
+    fs::write(output_path, &device.data).map_err(|e| BuildError::io(output_path, e))?;
+
+    Ok(BuildSummary { file_count: device.report.files.len(), size: device.data.len() })
+}
+
+/// Sets up a builder with everything from the build settings.
+fn create_builder(args: &BuildSettings) -> Result<DeviceBuilder> {
+    let mut builder = DeviceBuilder::new(TemplateSource::File(args.template.clone()))
+        .force_device_type(args.force_device_type)
+        .include_hidden(args.include_hidden);
+    if let Some(device_type) = &args.device_type {
+        builder = builder.device_type(device_type.clone());
+    }
+    if let Some(meta) = args.meta {
+        builder = builder.meta(meta);
+    }
+    for include in &args.include {
+        builder = match split_include(include) {
+            (path, Some(name)) => builder.include_as(path, name),
+            (path, None) => builder.include(path),
+        };
+    }
+    if let Some(name) = &args.include_stdin {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data).context("Cannot read from stdin")?;
+        builder = builder.include_data(name, data, "<stdin>");
+    }
+    for include in &args.include_zip {
+        let (path, sub_path) = split_zip_include(include);
+        builder = builder.include_zip(path, sub_path.map(str::to_string));
+    }
+    for pattern in &args.exclude {
+        builder = builder.exclude(pattern);
+    }
+    builder = builder.discover_dependencies(args.discover_dependencies);
+    for search_root in &args.search_roots {
+        builder = builder.search_root(search_root);
+    }
+    match (&args.modification_date, source_date_epoch()?) {
+        (Some(modification_date), _) => builder = builder.modification_date(modification_date.clone()),
+        (None, Some(source_date_epoch)) => builder = builder.modification_date(ModificationDate::Fixed(source_date_epoch)),
+        (None, None) => {}
+    }
+
+    Ok(builder)
+}
+
+/// The `SOURCE_DATE_EPOCH` environment variable used by reproducible builds, see
+/// <https://reproducible-builds.org/specs/source-date-epoch/>
+fn source_date_epoch() -> Result<Option<SystemTime>> {
+    let Ok(value) = std::env::var("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+
+    let source_date_epoch = value.trim().parse()
+        .ok()
+        .and_then(|seconds| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(seconds)))
+        .with_context(|| format!("SOURCE_DATE_EPOCH must be a number of seconds, found '{}'", value))?;
+
+    Ok(Some(source_date_epoch))
+}
@@ -17,6 +17,7 @@
 
 use crate::max_filetypes::ProjectContentSection::*;
 
+/// The section of a patcher's `project.contents` a file is listed in.
 pub enum ProjectContentSection {
     Patchers,
     Media,
@@ -26,8 +27,11 @@ pub enum ProjectContentSection {
     Other,
 }
 
+/// How Max classifies a file, derived from its extension.
 pub struct MaxFileType {
+    /// Stored in the `type` footer field and the dependency cache, e.g. `TEXT` or `WAVE`
     pub four_character_code: String,
+    /// The `kind` of the file in the project contents, e.g. `javascript` or `audiofile`
     pub project_file_type: String,
     pub project_content_section: ProjectContentSection,
 }
@@ -46,6 +50,8 @@ impl MaxFileType {
     }
 }
 
+/// Looks up the file type for an extension (without the dot, in any case).
+/// Unknown extensions are treated as generic data files.
 pub fn determine_file_type(extension: &str) -> MaxFileType {
     match extension.to_ascii_lowercase().as_str() {
         "aif" => MaxFileType::new("AIFF", "audiofile", Media),
@@ -15,15 +15,18 @@
  */
 
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::{BufMut, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
-use std::path::Path;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use std::{env, fs};
-use crate::max_filetypes::{determine_file_type, ProjectContentSection};
+use crate::amxd_builder::{build_unfrozen_amxd, DeviceType};
+use crate::amxd_reader::{parse_amxd, AmxdFile};
+use crate::error::BuildError;
+use crate::max_filetypes::{determine_file_type, MaxFileType, ProjectContentSection};
 
 #[derive(Serialize, Deserialize)]
 struct ProjectFile {
@@ -39,33 +42,177 @@ impl ProjectFile {
 
 #[derive(Serialize, Deserialize, Default)]
 struct ProjectContents {
-    patchers: HashMap<String, ProjectFile>,
-    media: HashMap<String, ProjectFile>,
-    code: HashMap<String, ProjectFile>,
-    data: HashMap<String, ProjectFile>,
-    externals: HashMap<String, ProjectFile>,
-    other: HashMap<String, ProjectFile>,
+    patchers: BTreeMap<String, ProjectFile>,
+    media: BTreeMap<String, ProjectFile>,
+    code: BTreeMap<String, ProjectFile>,
+    data: BTreeMap<String, ProjectFile>,
+    externals: BTreeMap<String, ProjectFile>,
+    other: BTreeMap<String, ProjectFile>,
 }
 
-pub fn preprocess_template_file(template_path: &str, files: &[String]) -> Result<String> {
-    let mut maxpat_json = parse_maxpat_json(template_path)?;
-    let project = maxpat_json["patcher"]["project"].as_object_mut().unwrap();
+/// An entry of the patcher's `dependency_cache`, which Max uses to find the files of a device when loading it.
+#[derive(Serialize, Deserialize)]
+struct DependencyCacheEntry {
+    name: String,
+    bootpath: String,
+    #[serde(rename = "type")]
+    file_type: String,
+    implicit: u8,
+}
+
+impl DependencyCacheEntry {
+    pub fn new(name: &str, file_type: &MaxFileType) -> Self {
+        // Embedded files live next to the device, so there is no machine specific path to store
+        Self { name: name.to_string(), bootpath: ".".to_string(), file_type: file_type.four_character_code.clone(), implicit: 1 }
+    }
+}
+
+/// Reads a template, which is either an unfrozen device or a plain patcher (`.maxpat` / `.json`).
+/// Plain patchers get wrapped in a device header, so they need an explicit device type.
+pub fn read_template(template_path: &str, device_type: Option<&DeviceType>, meta: Option<u32>) -> Result<AmxdFile> {
+    let contents = fs::read(template_path).map_err(|e| BuildError::io(template_path, e))?;
+
+    parse_template(template_path, Bytes::from(contents), device_type, meta)
+}
+
+/// Same as [`read_template`], for a template that is already loaded. The name is used to
+/// tell plain patchers from devices.
+pub fn parse_template(
+    template_name: &str,
+    contents: Bytes,
+    device_type: Option<&DeviceType>,
+    meta: Option<u32>,
+) -> Result<AmxdFile> {
+    if !is_plain_patcher(template_name) {
+        let mut template = parse_amxd(contents)
+            .map_err(|e| BuildError::template_parse(template_name, e))?;
+        if let Some(meta) = meta {
+            template.meta = meta;
+        }
+        return Ok(template);
+    }
+
+    let device_type = device_type
+        .ok_or_else(|| anyhow!("A device type is required when using a plain patcher as template"))?;
+    let mut maxpat_json: Value = serde_json::from_slice(&contents)
+        .map_err(|e| BuildError::template_parse(template_name, anyhow!("not valid JSON ({})", e)))?;
+    add_missing_project(&mut maxpat_json, device_type)
+        .map_err(|e| BuildError::template_parse(template_name, e))?;
+
+    parse_amxd(build_unfrozen_amxd(device_type, &meta.unwrap_or(0), serialize_patcher(&maxpat_json)?))
+}
+
+/// Parses the JSON of a patcher, ignoring the trailing NUL bytes Max writes.
+pub fn parse_patcher_data(data: &[u8]) -> serde_json::Result<Value> {
+    let end = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    serde_json::from_slice(&data[..end])
+}
+
+/// Serializes a patcher the way it is stored in a device, terminated by a NUL byte.
+pub fn serialize_patcher(maxpat_json: &Value) -> Result<Bytes> {
+    let mut buf = BytesMut::new();
+    buf.put(Bytes::from(serde_json::to_vec_pretty(maxpat_json)?));
+    buf.put_u8(0);
+
+    Ok(buf.freeze())
+}
+
+fn is_plain_patcher(template_path: &str) -> bool {
+    let extension = Path::new(template_path).extension().and_then(|extension| extension.to_str());
+    matches!(extension.map(|extension| extension.to_ascii_lowercase()).as_deref(), Some("maxpat" | "json"))
+}
+
+/// Patchers that were not saved as a device don't have a project section, so a minimal one is added.
+fn add_missing_project(maxpat_json: &mut Value, device_type: &DeviceType) -> Result<()> {
+    let patcher = maxpat_json["patcher"].as_object_mut()
+        .ok_or_else(|| anyhow!("Template has no 'patcher' section"))?;
+
+    if !patcher.contains_key("project") {
+        let amxd_type = u32::from_be_bytes(device_type.to_header_representation()[..4].try_into()?);
+        patcher.insert("project".to_string(), json!({
+            "version": 1,
+            "amxdtype": amxd_type,
+            "readonly": 0,
+            "devpathtype": 0,
+            "devpath": ".",
+            "sortmode": 0,
+            "viewmode": 0,
+            "includepackages": 0,
+        }));
+    }
+
+    Ok(())
+}
+
+/// Creates a new temporary directory for the intermediate files of a build.
+pub fn create_work_dir() -> Result<PathBuf> {
+    let mut work_dir = env::temp_dir();
+    work_dir.push("maxbuild");
+    work_dir.push(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_nanos()
+        .to_string());
+    fs::create_dir_all(&work_dir)?;
+
+    Ok(work_dir)
+}
+
+/// Lists the given files in the project contents and dependency cache of the template, and writes
+/// it to the work directory as the main patcher. Returns the path of the written file.
+pub fn preprocess_template_file(
+    work_dir: &Path,
+    template_path: &str,
+    mut maxpat_json: Value,
+    files: &[String],
+) -> Result<String> {
+    if !maxpat_json["patcher"]["project"].is_object() {
+        let error = anyhow!("the patcher has no 'project' section");
+        return Err(BuildError::template_parse(template_path, error).into());
+    }
+    update_project_contents(&mut maxpat_json, files)?;
+
+    write_template(work_dir, template_path, &maxpat_json)
+}
+
+/// Replaces the project contents and the dependency cache of a patcher with the given files
+/// (paths or plain file names).
+pub fn update_project_contents(maxpat_json: &mut Value, files: &[String]) -> Result<()> {
+    let mut file_types = Vec::new();
+    for file in files {
+        file_types.push(file_type(file)?);
+    }
 
-    let project_contents = build_prject_contents(files);
+    let project = maxpat_json["patcher"]["project"].as_object_mut()
+        .ok_or_else(|| anyhow!("Template patcher has no 'project' section"))?;
+    let project_contents = build_prject_contents(&file_types);
     project.insert("contents".to_string(), serde_json::to_value(project_contents)?, );
 
-    write_template(template_path, &maxpat_json)
+    let dependency_cache = build_dependency_cache(&file_types);
+    maxpat_json["patcher"]["dependency_cache"] = serde_json::to_value(dependency_cache)?;
+
+    Ok(())
 }
 
-fn build_prject_contents(files: &[String]) -> ProjectContents {
+fn file_type(file: &str) -> Result<(&str, MaxFileType), BuildError> {
+    let path = Path::new(file);
+    let (Some(file_name), Some(extension)) = (
+        path.file_name().and_then(|file_name| file_name.to_str()),
+        path.extension().and_then(|extension| extension.to_str()),
+    ) else {
+        return Err(BuildError::UnsupportedFile {
+            path: path.to_path_buf(),
+            reason: "the file has no extension to determine its type from".to_string(),
+        });
+    };
+
+    Ok((file_name, determine_file_type(extension)))
+}
+
+fn build_prject_contents(file_types: &[(&str, MaxFileType)]) -> ProjectContents {
     let mut contents = ProjectContents::default();
 
-    for file in files {
-        let path = Path::new(file);
-        let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
-        let file_type = determine_file_type(path.extension().unwrap().to_str().unwrap());
-        
-        let section: &mut HashMap<String, ProjectFile> = match file_type.project_content_section {
+    for (file_name, file_type) in file_types {
+        let section: &mut BTreeMap<String, ProjectFile> = match file_type.project_content_section {
             ProjectContentSection::Patchers => &mut contents.patchers,
             ProjectContentSection::Media => &mut contents.media,
             ProjectContentSection::Code => &mut contents.code,
@@ -74,36 +221,36 @@ fn build_prject_contents(files: &[String]) -> ProjectContents {
             ProjectContentSection::Other => &mut contents.other,
         };
 
-        section.insert(file_name, ProjectFile::new(&file_type.project_file_type));
+        section.insert(file_name.to_string(), ProjectFile::new(&file_type.project_file_type));
     }
 
     contents
 }
 
-fn write_template(template_path: &str, template: &Value) -> Result<String> {
-    let mut output_file = env::temp_dir();
-    output_file.push("maxbuild");
-    output_file.push(SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_nanos()
-        .to_string());
-    fs::create_dir_all(&output_file)?;
+/// Max's own freeze lists the embedded files sorted by name.
+fn build_dependency_cache(file_types: &[(&str, MaxFileType)]) -> Vec<DependencyCacheEntry> {
+    let mut dependency_cache: Vec<_> = file_types.iter()
+        .map(|(file_name, file_type)| DependencyCacheEntry::new(file_name, file_type))
+        .collect();
+    dependency_cache.sort_by(|a, b| a.name.cmp(&b.name));
 
-    let mut buf = BytesMut::new();
-    buf.put(Bytes::from(serde_json::to_vec_pretty(&template)?));
-    buf.put_u8(0);
+    dependency_cache
+}
 
-    output_file.push(Path::new(template_path).file_name().unwrap());
-    fs::write(&output_file, &buf)?;
+fn write_template(work_dir: &Path, template_path: &str, template: &Value) -> Result<String> {
+    let file_name = Path::new(template_path).file_name().ok_or_else(|| BuildError::UnsupportedFile {
+        path: PathBuf::from(template_path),
+        reason: "the template has no file name to name the main patcher after".to_string(),
+    })?;
 
-    Ok(output_file.to_str().unwrap().to_string())
-}
+    // The main patcher is always stored as a device, even if the template is a plain patcher
+    let output_file = work_dir.join(Path::new(file_name).with_extension("amxd"));
+    fs::write(&output_file, serialize_patcher(template)?).map_err(|e| BuildError::io(&output_file, e))?;
 
-fn parse_maxpat_json(template_path: &str) -> Result<Value> {
-    let file_contents = fs::read(template_path)?;
-    // Skip header, as well as the null byte at the end:
-    let maxpat_contents = &file_contents[32..file_contents.len() - 1];
-    let json_contents = serde_json::from_slice(maxpat_contents)?;
+    let output_path = output_file.to_str().ok_or_else(|| BuildError::UnsupportedFile {
+        path: output_file.clone(),
+        reason: "the path is not valid UTF-8".to_string(),
+    })?;
 
-    Ok(json_contents)
+    Ok(output_path.to_string())
 }
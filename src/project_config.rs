@@ -0,0 +1,297 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use crate::args::{parse_modification_date, BuildArgs};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use maxbuild::{DeviceType, ModificationDate};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const PROJECT_CONFIG_FILE_NAME: &str = "maxbuild.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub template: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_device_type")]
+    pub device_type: Option<DeviceType>,
+    pub output: Option<String>,
+    pub meta: Option<u32>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub include_zip: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub include_hidden: bool,
+    #[serde(default, deserialize_with = "deserialize_modification_date")]
+    pub modification_date: Option<ModificationDate>,
+    #[serde(default)]
+    pub discover_dependencies: bool,
+    #[serde(default)]
+    pub search_roots: Vec<String>,
+
+    /// Named lists of includes that can be shared between the devices of a workspace
+    #[serde(default)]
+    pub include_sets: BTreeMap<String, Vec<String>>,
+    /// The devices of a workspace. If empty, the project file describes a single device.
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceTarget>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DeviceTarget {
+    pub name: String,
+    pub template: String,
+    #[serde(default, deserialize_with = "deserialize_device_type")]
+    pub device_type: Option<DeviceType>,
+    pub output: String,
+    pub meta: Option<u32>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub include_zip: Vec<String>,
+    #[serde(default)]
+    pub include_sets: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub include_hidden: Option<bool>,
+}
+
+/// The final settings of a build, after merging the project file with the command line.
+pub struct BuildSettings {
+    pub name: String,
+    pub template: String,
+    /// Not needed when only checking the device. The build fails without it.
+    pub output_file: Option<String>,
+    /// An explicitly requested device type. If not set, the type of the template is used.
+    pub device_type: Option<DeviceType>,
+    pub force_device_type: bool,
+    pub meta: Option<u32>,
+    pub include: Vec<String>,
+    pub include_zip: Vec<String>,
+    /// Name of a file to read from stdin
+    pub include_stdin: Option<String>,
+    pub exclude: Vec<String>,
+    pub include_hidden: bool,
+    pub modification_date: Option<ModificationDate>,
+    pub discover_dependencies: bool,
+    pub search_roots: Vec<String>,
+}
+
+pub fn resolve_build_settings(args: BuildArgs) -> Result<Vec<BuildSettings>> {
+    let config_path = match &args.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => find_project_config(&std::env::current_dir()?),
+    };
+    let config = match &config_path {
+        Some(path) => load_project_config(path)?,
+        None => ProjectConfig::default(),
+    };
+
+    if config.devices.is_empty() {
+        if !args.target.is_empty() {
+            bail!("--target can only be used with a workspace project file");
+        }
+
+        resolve_single_device(args, config).map(|settings| vec![settings])
+    } else {
+        resolve_workspace(args, config)
+    }
+}
+
+fn resolve_single_device(args: BuildArgs, config: ProjectConfig) -> Result<BuildSettings> {
+    let include = if args.include.is_empty() { config.include } else { args.include };
+    let include_zip = if args.include_zip.is_empty() { config.include_zip } else { args.include_zip };
+    let output_file = args.output_file.or(config.output);
+    let template = args.template.or(config.template)
+        .ok_or_else(|| missing_setting("--template", "template"))?;
+
+    Ok(BuildSettings {
+        name: Path::new(output_file.as_ref().unwrap_or(&template)).file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        template,
+        output_file,
+        device_type: args.device_type.or(config.device_type),
+        force_device_type: args.force_device_type,
+        meta: args.meta.or(config.meta),
+        include,
+        include_zip,
+        include_stdin: args.include_stdin,
+        exclude: [config.exclude, args.exclude].concat(),
+        include_hidden: args.include_hidden || config.include_hidden,
+        modification_date: args.modification_date.or(config.modification_date),
+        discover_dependencies: args.discover_dependencies || config.discover_dependencies,
+        search_roots: if args.search_root.is_empty() { config.search_roots } else { args.search_root },
+    })
+}
+
+fn resolve_workspace(args: BuildArgs, config: ProjectConfig) -> Result<Vec<BuildSettings>> {
+    let has_device_flags = args.template.is_some()
+        || args.output_file.is_some()
+        || args.device_type.is_some()
+        || args.meta.is_some()
+        || !args.include.is_empty()
+        || !args.include_zip.is_empty()
+        || args.include_stdin.is_some();
+    if has_device_flags {
+        bail!("--template, --output-file, --device-type, --meta, --include, --include-zip and --include-stdin cannot be used when building a workspace");
+    }
+
+    // A top-level template or output would have to be shared by all devices, which can't work
+    if config.template.is_some() || config.output.is_some() {
+        bail!("'template' and 'output' have to be set for each [[device]] of a workspace, not at the top level");
+    }
+
+    for name in &args.target {
+        if !config.devices.iter().any(|device| &device.name == name) {
+            bail!("Unknown target '{}'", name);
+        }
+    }
+
+    config.devices.into_iter()
+        .filter(|device| args.target.is_empty() || args.target.contains(&device.name))
+        .map(|device| {
+            let mut include = [config.include.as_slice(), device.include.as_slice()].concat();
+            for set_name in &device.include_sets {
+                let set = config.include_sets.get(set_name).ok_or_else(|| {
+                    anyhow!("Device '{}' uses unknown include set '{}'", device.name, set_name)
+                })?;
+                include.extend(set.iter().cloned());
+            }
+
+            let exclude = [config.exclude.as_slice(), device.exclude.as_slice(), args.exclude.as_slice()].concat();
+
+            Ok(BuildSettings {
+                name: device.name,
+                template: device.template,
+                output_file: Some(device.output),
+                device_type: device.device_type.or(config.device_type.clone()),
+                force_device_type: args.force_device_type,
+                meta: device.meta.or(config.meta),
+                include,
+                include_zip: [config.include_zip.as_slice(), device.include_zip.as_slice()].concat(),
+                include_stdin: None,
+                exclude,
+                include_hidden: args.include_hidden || device.include_hidden.unwrap_or(config.include_hidden),
+                modification_date: args.modification_date.clone().or(config.modification_date.clone()),
+                discover_dependencies: args.discover_dependencies || config.discover_dependencies,
+                search_roots: if args.search_root.is_empty() { config.search_roots.clone() } else { args.search_root.clone() },
+            })
+        })
+        .collect()
+}
+
+/// Looks for a project file in the given directory and all of its parents.
+pub fn find_project_config(start_dir: &Path) -> Option<PathBuf> {
+    start_dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Loads a project file. All paths in it are resolved relative to the directory of the file.
+pub fn load_project_config(path: &Path) -> Result<ProjectConfig> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read project file '{}'", path.display()))?;
+    let mut config: ProjectConfig = toml::from_str(&contents)
+        .with_context(|| format!("Cannot parse project file '{}'", path.display()))?;
+
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    config.template = config.template.map(|template| resolve_path(base_dir, &template));
+    config.output = config.output.map(|output| resolve_path(base_dir, &output));
+    config.search_roots = config.search_roots.iter().map(|root| resolve_path(base_dir, root)).collect();
+    config.include = resolve_includes(base_dir, &config.include);
+    config.include_zip = resolve_zip_includes(base_dir, &config.include_zip);
+
+    for include_set in config.include_sets.values_mut() {
+        *include_set = resolve_includes(base_dir, include_set);
+    }
+    for device in &mut config.devices {
+        device.template = resolve_path(base_dir, &device.template);
+        device.output = resolve_path(base_dir, &device.output);
+        device.include = resolve_includes(base_dir, &device.include);
+        device.include_zip = resolve_zip_includes(base_dir, &device.include_zip);
+    }
+
+    Ok(config)
+}
+
+fn resolve_path(base_dir: &Path, path: &str) -> String {
+    base_dir.join(path).to_string_lossy().to_string()
+}
+
+fn resolve_includes(base_dir: &Path, includes: &[String]) -> Vec<String> {
+    includes.iter()
+        .map(|include| match split_include(include) {
+            (path, Some(name)) => format!("{}={}", resolve_path(base_dir, path), name),
+            (path, None) => resolve_path(base_dir, path),
+        })
+        .collect()
+}
+
+fn resolve_zip_includes(base_dir: &Path, includes: &[String]) -> Vec<String> {
+    includes.iter()
+        .map(|include| match split_zip_include(include) {
+            (path, Some(sub_path)) => format!("{}#{}", resolve_path(base_dir, path), sub_path),
+            (path, None) => resolve_path(base_dir, path),
+        })
+        .collect()
+}
+
+/// Splits a zip include of the form `<archive>#<sub-path>` into the archive and the path inside of it.
+pub fn split_zip_include(include: &str) -> (&str, Option<&str>) {
+    match include.split_once('#') {
+        Some((path, sub_path)) => (path, Some(sub_path)),
+        None => (include, None),
+    }
+}
+
+/// Splits an include of the form `<path>=<name>` into the path on disk and the name inside the device.
+/// A path that exists as given is never split, even if it contains a `=`.
+pub fn split_include(include: &str) -> (&str, Option<&str>) {
+    if Path::new(include).exists() {
+        return (include, None);
+    }
+
+    match include.rsplit_once('=') {
+        Some((path, name)) => (path, Some(name)),
+        None => (include, None),
+    }
+}
+
+pub fn missing_setting(flag: &str, key: &str) -> anyhow::Error {
+    anyhow!("No value given for {} (set '{}' in {} or pass it on the command line)", flag, key, PROJECT_CONFIG_FILE_NAME)
+}
+
+fn deserialize_device_type<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DeviceType>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    DeviceType::from_str(&name, true)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_modification_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ModificationDate>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_modification_date(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
@@ -0,0 +1,323 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_builder::{build_frozen_amxd, DeviceType, FROZEN_HEADER_LENGTH};
+use maxbuild::amxd_fields::read_frozen_device_field;
+use maxbuild::amxd_footer::{build_footer, parse_footer_leniently};
+use crate::args::RepairArgs;
+use maxbuild::device_builder::{file_flag, new_device_file, rebuild_device, DeviceFile, DeviceFileFlag};
+use crate::update::write_atomically;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{Buf, Bytes};
+use std::fs;
+use std::path::Path;
+
+/// A recognizable piece of data found while scanning the data region
+struct Payload {
+    start: usize,
+    end: usize,
+    extension: &'static str,
+}
+
+pub fn repair_device(args: &RepairArgs) -> Result<()> {
+    let data = Bytes::from(fs::read(&args.device).with_context(|| format!("Cannot read device file '{}'", args.device))?);
+    let (device_type, meta, body) = read_header_leniently(data)?;
+    if !body.starts_with(b"mx@c") {
+        bail!("'{}' is not a frozen device", args.device);
+    }
+
+    let mut report = Vec::new();
+    let footer_location = read_footer_location(&body).filter(|location| {
+        (FROZEN_HEADER_LENGTH..=body.len()).contains(location)
+    });
+    let data_end = footer_location.unwrap_or(body.len());
+
+    // Entries of the original footer are kept as long as their data is intact
+    let mut entries: Vec<(DeviceFile, Bytes)> = Vec::new();
+    let mut covered: Vec<(usize, usize)> = Vec::new();
+    match footer_location {
+        Some(location) => {
+            let (files, problems) = parse_footer_leniently(body.slice(location..));
+            for problem in problems {
+                report.push(format!("footer    {}", problem));
+            }
+
+            for file in files {
+                let start = file.data_offset as usize;
+                let end = start + file.data_size as usize;
+                let overlaps = covered.iter().any(|(s, e)| start < *e && *s < end);
+                if start < FROZEN_HEADER_LENGTH || end > data_end || overlaps {
+                    report.push(format!("lost      {} (invalid offset {} / size {})", file.file_name, start, file.data_size));
+                    continue;
+                }
+
+                report.push(format!("salvaged  {} (from footer)", file.file_name));
+                covered.push((start, end));
+                entries.push((file, body.slice(start..end)));
+            }
+        }
+        None => report.push("footer    location invalid, scanning data region".to_string()),
+    }
+
+    // Everything that isn't covered by an intact footer entry is scanned for known payloads
+    covered.sort();
+    let mut gaps = Vec::new();
+    let mut position = FROZEN_HEADER_LENGTH;
+    for (start, end) in covered.iter().copied().chain([(data_end, data_end)]) {
+        if start > position {
+            gaps.push((position, start));
+        }
+        position = position.max(end);
+    }
+
+    let mut recovered_count = 0;
+    for (gap_start, gap_end) in gaps {
+        for payload in scan_payloads(&body, gap_start, gap_end) {
+            let bytes = body.slice(payload.start..payload.end);
+            if payload.extension.is_empty() {
+                report.push(format!("lost      {} unrecognized bytes at offset {}", bytes.len(), payload.start));
+                continue;
+            }
+
+            recovered_count += 1;
+            let name = format!("recovered-{:03}.{}", recovered_count, payload.extension);
+            let file = new_device_file(&name, file_flag(&name), bytes.len())
+                .ok_or_else(|| anyhow!("Cannot describe recovered file '{}'", name))?;
+
+            report.push(format!("recovered {} ({} bytes at offset {})", name, bytes.len(), payload.start));
+            entries.push((file, bytes));
+        }
+    }
+
+    ensure_main_file(&mut entries, &args.device, &mut report)?;
+
+    let device_data = rebuild_device(entries)?;
+    let repaired_device = build_frozen_amxd(&device_type, &meta, device_data.data, build_footer(&device_data.files));
+
+    let output = args.output.clone().unwrap_or_else(|| default_output_path(&args.device));
+    write_atomically(&output, &repaired_device)?;
+
+    let report_path = format!("{}.report.txt", output);
+    fs::write(&report_path, report.join("\n") + "\n")
+        .with_context(|| format!("Cannot write report '{}'", report_path))?;
+
+    for line in &report {
+        println!("{}", line);
+    }
+    println!();
+    println!("Wrote repaired device to '{}' and report to '{}'", output, report_path);
+
+    Ok(())
+}
+
+/// Reads the header fields like the regular reader, but accepts a truncated `ptch` field.
+fn read_header_leniently(mut data: Bytes) -> Result<(DeviceType, u32, Bytes)> {
+    let mut device_type = None;
+    let mut meta = None;
+
+    while data.remaining() >= 8 {
+        let field_type = data.split_to(4);
+        let length = data.get_u32_le() as usize;
+        let field_data = data.split_to(length.min(data.len()));
+
+        match &field_type[..] {
+            b"ampf" => device_type = Some(DeviceType::from_header_representation(&field_data)?),
+            b"meta" if field_data.len() == 4 => meta = Some(field_data.clone().get_u32_le()),
+            b"ptch" => {
+                return Ok((
+                    device_type.ok_or_else(|| anyhow!("Device is missing the 'ampf' field"))?,
+                    meta.unwrap_or(0),
+                    field_data,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    bail!("Device is missing the 'ptch' field")
+}
+
+fn read_footer_location(body: &Bytes) -> Option<usize> {
+    let header = read_frozen_device_field(&mut body.clone()).ok()?;
+    if header.data.len() < 8 {
+        return None;
+    }
+
+    Some(header.data.clone().get_u64() as usize)
+}
+
+/// Makes sure that exactly one file is flagged as the main patcher, picking the first
+/// recovered patcher if the original main file was lost.
+fn ensure_main_file(entries: &mut [(DeviceFile, Bytes)], device_path: &str, report: &mut Vec<String>) -> Result<()> {
+    if entries.iter().any(|(file, _)| file.flag == DeviceFileFlag::MainFile) {
+        return Ok(());
+    }
+
+    // Only the main patcher of a device has a project section, abstractions usually don't
+    let is_patcher = |(file, _): &(DeviceFile, Bytes)| file.file_name.ends_with(".maxpat");
+    let has_project = |(_, bytes): &(DeviceFile, Bytes)| bytes.windows(9).any(|window| window == b"\"project\"");
+    let main_index = entries.iter().position(|entry| is_patcher(entry) && has_project(entry))
+        .or_else(|| entries.iter().position(is_patcher))
+        .ok_or_else(|| anyhow!("Could not recover a main patcher, the device cannot be repaired"))?;
+    let main_file = &mut entries[main_index].0;
+
+    let main_name = Path::new(device_path).with_extension("amxd");
+    let main_name = main_name.file_name().unwrap().to_string_lossy().to_string();
+    report.push(format!("main      {} is used as main patcher {}", main_file.file_name, main_name));
+
+    main_file.file_name = main_name;
+    main_file.file_type = "amxd".to_string();
+    main_file.flag = DeviceFileFlag::MainFile;
+
+    Ok(())
+}
+
+fn default_output_path(device_path: &str) -> String {
+    let path = Path::new(device_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!("{}.repaired.amxd", stem)).to_string_lossy().to_string()
+}
+
+/// Splits a region of the data into recognizable payloads. Payloads whose length can't be
+/// determined from their own headers extend until the next recognized payload.
+fn scan_payloads(data: &[u8], start: usize, end: usize) -> Vec<Payload> {
+    let mut payloads: Vec<Payload> = Vec::new();
+    let mut unknown_start = start;
+    let mut position = start;
+
+    while position < end {
+        let Some((extension, length)) = detect_payload(&data[position..end]) else {
+            position += 1;
+            continue;
+        };
+
+        if position > unknown_start {
+            push_unknown(&mut payloads, data, unknown_start, position);
+        }
+
+        let payload_end = match length {
+            Some(length) => position + length,
+            None => next_payload_start(data, position + 1, end),
+        };
+        payloads.push(Payload { start: position, end: payload_end, extension });
+
+        position = payload_end;
+        unknown_start = payload_end;
+    }
+
+    if end > unknown_start {
+        push_unknown(&mut payloads, data, unknown_start, end);
+    }
+
+    payloads
+}
+
+fn next_payload_start(data: &[u8], start: usize, end: usize) -> usize {
+    (start..end)
+        .find(|position| detect_payload(&data[*position..end]).is_some())
+        .unwrap_or(end)
+}
+
+/// Data without a known signature is kept if it is text (scripts, shaders, ...), otherwise it is lost.
+fn push_unknown(payloads: &mut Vec<Payload>, data: &[u8], start: usize, end: usize) {
+    let bytes = &data[start..end];
+    if bytes.iter().all(|b| *b == 0) {
+        return;
+    }
+
+    let extension = if std::str::from_utf8(bytes).is_ok() { "txt" } else { "" };
+    payloads.push(Payload { start, end, extension });
+}
+
+fn detect_payload(data: &[u8]) -> Option<(&'static str, Option<usize>)> {
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        let length = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?) as usize + 8;
+        return Some(("wav", Some(length.min(data.len()))));
+    }
+    if data.starts_with(b"FORM") && matches!(data.get(8..12), Some(b"AIFF" | b"AIFC")) {
+        let length = u32::from_be_bytes(data.get(4..8)?.try_into().ok()?) as usize + 8;
+        return Some(("aif", Some(length.min(data.len()))));
+    }
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some(("png", png_length(data)));
+    }
+    if data.starts_with(b"\xff\xd8\xff") {
+        return Some(("jpg", None));
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some(("gif", None));
+    }
+    if data.starts_with(b"fLaC") {
+        return Some(("flac", None));
+    }
+    if data.starts_with(b"{") && is_patcher_start(data) {
+        return Some(("maxpat", json_length(data)));
+    }
+
+    None
+}
+
+fn is_patcher_start(data: &[u8]) -> bool {
+    let rest = &data[1..data.len().min(64)];
+    let first = rest.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(rest.len());
+
+    rest[first..].starts_with(b"\"patcher\"")
+}
+
+/// Finds the end of a JSON object by matching braces, including the line break and null byte that may follow it.
+fn json_length(data: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, byte) in data.iter().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            b'{' if !in_string => depth += 1,
+            b'}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    let end = index + 1;
+                    let whitespace = data[end..].iter().take_while(|b| b.is_ascii_whitespace()).count();
+                    let end = end + whitespace;
+                    return Some(if data.get(end) == Some(&0) { end + 1 } else { end });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Walks the PNG chunks up to and including the `IEND` chunk.
+fn png_length(data: &[u8]) -> Option<usize> {
+    let mut position = 8;
+    loop {
+        let length = u32::from_be_bytes(data.get(position..position + 4)?.try_into().ok()?) as usize;
+        let chunk_type = data.get(position + 4..position + 8)?;
+        position += 12 + length;
+
+        if chunk_type == b"IEND" {
+            return (position <= data.len()).then_some(position);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_reader::{read_amxd, AmxdPatcher};
+use maxbuild::device_builder::DeviceFileFlag;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+/// Prints a stable, human-readable rendering of a device, meant to be used
+/// as a git textconv driver. Anything that changes between two builds of the
+/// same sources (offsets, timestamps) is left out on purpose.
+pub fn print_textconv(device_path: &str) -> Result<()> {
+    let device = read_amxd(device_path)?;
+
+    println!("device-type: {}", device.device_type);
+    println!("meta: {}", device.meta);
+
+    if let AmxdPatcher::Frozen(frozen_device) = &device.patcher {
+        println!("frozen: yes");
+        println!();
+        println!("embedded files:");
+
+        let mut files: Vec<_> = frozen_device.files.iter()
+            .filter(|file| file.flag != DeviceFileFlag::MainFile)
+            .collect();
+        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+        for file in files {
+            let data = frozen_device.file_data(file)?;
+            println!(
+                "  {}  {}  {} bytes  sha256:{:x}",
+                file.file_type,
+                file.file_name,
+                data.len(),
+                Sha256::digest(&data)
+            );
+        }
+    } else {
+        println!("frozen: no");
+    }
+
+    // serde_json sorts object keys, so this is a canonical form of the patcher
+    println!();
+    println!("{}", serde_json::to_string_pretty(&device.patcher_json()?)?);
+
+    Ok(())
+}
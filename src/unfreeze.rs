@@ -0,0 +1,58 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_builder::build_unfrozen_amxd;
+use maxbuild::amxd_reader::{output_file_path, read_amxd, AmxdPatcher};
+use maxbuild::DeviceFileFlag;
+use anyhow::{bail, Context, Result};
+use std::fs;
+
+pub fn unfreeze_device(device_path: &str, output_dir: &str) -> Result<()> {
+    let device = read_amxd(device_path)?;
+    let frozen_device = match &device.patcher {
+        AmxdPatcher::Frozen(frozen_device) => frozen_device,
+        AmxdPatcher::Unfrozen(_) => bail!("'{}' is not a frozen device", device_path),
+    };
+
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Cannot create output directory '{}'", output_dir))?;
+
+    for file in &frozen_device.files {
+        let output_path = output_file_path(output_dir, &file.file_name)?;
+        let data = frozen_device.file_data(file)?;
+
+        // The main patcher is stored without its header, so it gets wrapped
+        // again to make it usable as an unfrozen device (and maxbuild template).
+        let contents = if file.flag == DeviceFileFlag::MainFile {
+            build_unfrozen_amxd(&device.device_type, &device.meta, data)
+        } else {
+            data
+        };
+
+        println!("Extracting file: {}", output_path.display());
+        fs::write(&output_path, &contents)
+            .with_context(|| format!("Cannot write file '{}'", output_path.display()))?;
+        fs::File::options()
+            .write(true)
+            .open(&output_path)?
+            .set_modified(file.modification_date)
+            .with_context(|| format!("Cannot set the modification time of '{}'", output_path.display()))?;
+    }
+
+    Ok(())
+}
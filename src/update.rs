@@ -0,0 +1,160 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_builder::{build_frozen_amxd, DeviceType};
+use maxbuild::amxd_footer::{build_footer, clamp_to_hfsplus_range};
+use maxbuild::amxd_reader::{read_amxd, AmxdPatcher};
+use crate::args::UpdateArgs;
+use maxbuild::device_builder::{
+    check_name_collisions, collision_key, file_flag, new_device_file, rebuild_device, DeviceFile, DeviceFileFlag,
+};
+use maxbuild::maxpat_project::{parse_patcher_data, read_template, serialize_patcher, update_project_contents};
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+pub fn update_device(args: &UpdateArgs) -> Result<()> {
+    let device = read_amxd(&args.device)?;
+    let frozen_device = match &device.patcher {
+        AmxdPatcher::Frozen(frozen_device) => frozen_device,
+        AmxdPatcher::Unfrozen(_) => bail!("'{}' is not a frozen device", args.device),
+    };
+    let main_file_name = frozen_device.main_file().map(|file| file.file_name.as_str());
+
+    let mut added_files = Vec::new();
+    for path in &args.add {
+        let name = Path::new(path).file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid file path '{}'", path))?;
+        check_main_file(name, main_file_name, args.allow_main_file)?;
+        added_files.push((name, path));
+    }
+    check_name_collisions(added_files.iter().map(|(name, path)| (*name, path.as_str())))?;
+    let mut removed_keys = Vec::new();
+    for name in &args.remove {
+        let key = collision_key(name);
+        if !frozen_device.files.iter().any(|file| collision_key(&file.file_name) == key) {
+            bail!("Device does not contain a file named '{}'", name);
+        }
+        check_main_file(name, main_file_name, args.allow_main_file)?;
+        removed_keys.push(key);
+    }
+
+    let mut main_file_replaced = false;
+    let mut entries: Vec<(DeviceFile, Bytes)> = Vec::new();
+    for file in &frozen_device.files {
+        let key = collision_key(&file.file_name);
+        if removed_keys.contains(&key) {
+            println!("Removing file: {}", file.file_name);
+            continue;
+        }
+
+        match added_files.iter().position(|(name, _)| collision_key(name) == key) {
+            Some(index) => {
+                let (_, path) = added_files.remove(index);
+                println!("Replacing file: {}", file.file_name);
+                main_file_replaced |= file.flag == DeviceFileFlag::MainFile;
+                entries.push(load_replacement(file, path, &device.device_type)?);
+            }
+            None => entries.push((file.clone(), frozen_device.file_data(file)?)),
+        }
+    }
+    for (_, path) in &added_files {
+        println!("Adding file: {}", path);
+        let bytes = Bytes::from(fs::read(path).with_context(|| format!("Cannot read file '{}'", path))?);
+        let mut file = new_device_file(path, file_flag(path), bytes.len())
+            .ok_or_else(|| anyhow!("Cannot determine the file type of '{}'", path))?;
+        file.modification_date = file_modification_date(path)?;
+        entries.push((file, bytes));
+    }
+
+    // Keep the project contents of the main patcher in sync with the embedded files
+    if !added_files.is_empty() || !args.remove.is_empty() || main_file_replaced {
+        update_main_file_contents(&mut entries)?;
+    }
+
+    let device_data = rebuild_device(entries)?;
+    let frozen_device = build_frozen_amxd(
+        &device.device_type,
+        &device.meta,
+        device_data.data,
+        build_footer(&device_data.files),
+    );
+
+    write_atomically(args.output.as_deref().unwrap_or(&args.device), &frozen_device)
+}
+
+fn check_main_file(name: &str, main_file_name: Option<&str>, allow_main_file: bool) -> Result<()> {
+    // Names are matched like replacements are, so a differently cased name can't get around the check
+    let is_main_file = main_file_name.is_some_and(|main_file_name| collision_key(name) == collision_key(main_file_name));
+    if is_main_file && !allow_main_file {
+        bail!("'{}' is the main patcher of the device (use --allow-main-file to change it)", name);
+    }
+
+    Ok(())
+}
+
+fn load_replacement(file: &DeviceFile, path: &str, device_type: &DeviceType) -> Result<(DeviceFile, Bytes)> {
+    let bytes = if file.flag == DeviceFileFlag::MainFile {
+        // The main patcher is stored without a device header
+        read_template(path, Some(device_type), None)?.patcher_data()?
+    } else {
+        Bytes::from(fs::read(path).with_context(|| format!("Cannot read file '{}'", path))?)
+    };
+
+    let mut replacement = file.clone();
+    replacement.modification_date = file_modification_date(path)?;
+
+    Ok((replacement, bytes))
+}
+
+fn file_modification_date(path: &str) -> Result<SystemTime> {
+    let modification_date = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Cannot read the modification date of '{}'", path))?;
+
+    Ok(clamp_to_hfsplus_range(&modification_date))
+}
+
+fn update_main_file_contents(entries: &mut [(DeviceFile, Bytes)]) -> Result<()> {
+    let file_names: Vec<String> = entries.iter()
+        .filter(|(file, _)| file.flag != DeviceFileFlag::MainFile)
+        .map(|(file, _)| file.file_name.clone())
+        .collect();
+
+    let Some((_, main_data)) = entries.iter_mut().find(|(file, _)| file.flag == DeviceFileFlag::MainFile) else {
+        return Ok(());
+    };
+
+    let mut maxpat_json = parse_patcher_data(main_data).context("Main patcher is not valid JSON")?;
+    update_project_contents(&mut maxpat_json, &file_names)?;
+    *main_data = serialize_patcher(&maxpat_json)?;
+
+    Ok(())
+}
+
+/// Writes to a temporary file next to the target first, so the target is never left half-written.
+pub fn write_atomically(path: &str, data: &[u8]) -> Result<()> {
+    let temp_path = format!("{}.maxbuild-tmp", path);
+    fs::write(&temp_path, data).with_context(|| format!("Cannot write file '{}'", temp_path))?;
+    fs::rename(&temp_path, path).with_context(|| format!("Cannot replace file '{}'", path))?;
+
+    Ok(())
+}
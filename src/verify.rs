@@ -0,0 +1,135 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::amxd_builder::FROZEN_HEADER_LENGTH;
+use maxbuild::amxd_fields::{read_frozen_device_field, read_header_field};
+use maxbuild::amxd_reader::{read_amxd, AmxdPatcher, FrozenDevice};
+use maxbuild::device_builder::DeviceFileFlag;
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, Bytes};
+use std::fs;
+
+pub fn verify_device(device_path: &str) -> Result<()> {
+    // Structural problems in the header fields or the footer are reported by the reader itself
+    let device = read_amxd(device_path)?;
+
+    let data = fs::read(device_path).with_context(|| format!("Cannot read device file '{}'", device_path))?;
+
+    let mut problems = Vec::new();
+    verify_header(Bytes::from(data), &mut problems);
+    if let AmxdPatcher::Frozen(frozen_device) = &device.patcher {
+        verify_frozen_device(frozen_device, &mut problems);
+    }
+    if let Err(e) = device.patcher_json() {
+        problems.push(format!("{:#}", e));
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("Problem: {}", problem);
+        }
+        bail!("'{}' failed verification with {} problem(s)", device_path, problems.len());
+    }
+
+    println!("'{}' is valid", device_path);
+    Ok(())
+}
+
+/// The reader stops at the `ptch` field, so anything after it is only noticed here.
+fn verify_header(mut data: Bytes, problems: &mut Vec<String>) {
+    while data.has_remaining() {
+        match read_header_field(&mut data) {
+            Ok(field) if field.field_type == "ptch" => break,
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+
+    if data.has_remaining() {
+        problems.push(format!("Found {} unexpected bytes after the 'ptch' field", data.remaining()));
+    }
+}
+
+fn verify_frozen_device(frozen_device: &FrozenDevice, problems: &mut Vec<String>) {
+    if let Ok(header) = read_frozen_device_field(&mut frozen_device.body.clone()) {
+        if header.data.len() + 8 != FROZEN_HEADER_LENGTH {
+            problems.push(format!(
+                "Frozen device header has a length of {} bytes, expected {}",
+                header.data.len() + 8, FROZEN_HEADER_LENGTH
+            ));
+        }
+    }
+
+    // The 'ptch' field is padded to a multiple of 4 bytes, which leaves up to 3 NUL bytes after the footer
+    let mut footer = frozen_device.body.slice(frozen_device.footer_location as usize..);
+    let is_padding = |rest: &Bytes| rest.len() < 4 && rest.iter().all(|b| *b == 0);
+    if read_frozen_device_field(&mut footer).is_ok() && !is_padding(&footer) {
+        problems.push(format!("Found {} unexpected bytes after the 'dlst' footer", footer.remaining()));
+    }
+
+    let header_length = FROZEN_HEADER_LENGTH as u64;
+    let data_end = frozen_device.footer_location as u64;
+    if data_end < header_length {
+        problems.push(format!("Footer location {} points into the frozen device header", data_end));
+    }
+
+    let mut regions = Vec::new();
+    for file in &frozen_device.files {
+        let start = file.data_offset as u64;
+        let end = start + file.data_size as u64;
+        if start < header_length || end > data_end {
+            problems.push(format!(
+                "Data of '{}' ({}..{}) lies outside of the data region ({}..{})",
+                file.file_name, start, end, header_length, data_end
+            ));
+        }
+
+        regions.push((start, end, &file.file_name));
+    }
+
+    // Compared with the region reaching furthest so far, which may cover several of the following ones
+    regions.sort();
+    let mut furthest: Option<(u64, &String)> = None;
+    for (start, end, name) in regions {
+        match furthest {
+            Some((furthest_end, furthest_name)) => {
+                if start < furthest_end {
+                    problems.push(format!("Data of '{}' overlaps with data of '{}'", name, furthest_name));
+                }
+                if end > furthest_end {
+                    furthest = Some((end, name));
+                }
+            }
+            None => furthest = Some((end, name)),
+        }
+    }
+
+    for file in &frozen_device.files {
+        if let DeviceFileFlag::Other(raw_flag) = file.flag {
+            // Newer versions of Max may use flags maxbuild doesn't know, so this is not a problem
+            eprintln!("Warning: '{}' has an unknown flag {}", file.file_name, raw_flag);
+        }
+    }
+
+    let main_file_count = frozen_device.files.iter()
+        .filter(|file| file.flag == DeviceFileFlag::MainFile)
+        .count();
+    if main_file_count != 1 {
+        problems.push(format!("Expected exactly one main patcher, found {}", main_file_count));
+    }
+}
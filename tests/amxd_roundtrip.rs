@@ -0,0 +1,138 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+use bytes::Bytes;
+use maxbuild::amxd_builder::build_unfrozen_amxd;
+use maxbuild::amxd_footer::{build_footer, parse_footer};
+use maxbuild::amxd_reader::{parse_amxd, AmxdPatcher, FrozenDevice};
+use maxbuild::device_builder::utc_time;
+use maxbuild::{BuildError, DeviceBuilder, DeviceFile, DeviceFileFlag, DeviceType, ModificationDate, TemplateSource};
+
+const PATCHER: &str = r#"{"patcher": {"project": {"version": 1}, "boxes": []}}"#;
+
+fn build_device(files: &[(&str, &[u8])]) -> Bytes {
+    let template = build_unfrozen_amxd(&DeviceType::MidiEffect, &7, Bytes::from(PATCHER));
+    let mut builder = DeviceBuilder::new(TemplateSource::Memory { name: "Dev.amxd".to_string(), data: template })
+        .modification_date(ModificationDate::Fixed(utc_time(2024, 5, 1, 12, 0, 0).unwrap()));
+    for (name, data) in files {
+        builder = builder.include_data(*name, data.to_vec(), *name);
+    }
+
+    builder.build().unwrap().data
+}
+
+fn frozen_device(data: Bytes) -> (DeviceType, u32, FrozenDevice) {
+    let device = parse_amxd(data).unwrap();
+    match device.patcher {
+        AmxdPatcher::Frozen(frozen_device) => (device.device_type, device.meta, frozen_device),
+        AmxdPatcher::Unfrozen(_) => panic!("Expected a frozen device"),
+    }
+}
+
+#[test]
+fn header_fields_survive_a_round_trip() {
+    let (device_type, meta, frozen_device) = frozen_device(build_device(&[]));
+
+    assert_eq!(device_type, DeviceType::MidiEffect);
+    assert_eq!(meta, 7);
+    assert!(frozen_device.body.starts_with(b"mx@c"));
+    assert_eq!(&frozen_device.body[frozen_device.footer_location as usize..][..4], b"dlst");
+}
+
+#[test]
+fn footer_entries_point_at_the_file_data() {
+    let files: [(&str, &[u8]); 3] = [("helper.js", b"post(1);"), ("kick.wav", b"RIFF...."), ("odd.txt", b"abc")];
+    let (_, _, frozen_device) = frozen_device(build_device(&files));
+
+    assert_eq!(frozen_device.files.len(), 4);
+    let main_file = frozen_device.main_file().unwrap();
+    assert_eq!(main_file.file_name, "Dev.amxd");
+    assert!(frozen_device.file_data(main_file).unwrap().starts_with(b"{"));
+
+    for (name, data) in files {
+        let file = frozen_device.files.iter().find(|file| file.file_name == name).unwrap();
+        assert_eq!(frozen_device.file_data(file).unwrap(), data);
+        assert_eq!(file.data_size as usize, data.len());
+        assert_eq!(file.modification_date, utc_time(2024, 5, 1, 12, 0, 0).unwrap());
+    }
+
+    let helper = frozen_device.files.iter().find(|file| file.file_name == "helper.js").unwrap();
+    assert_eq!(helper.flag, DeviceFileFlag::JSFile);
+    assert_eq!(helper.file_type, "TEXT");
+}
+
+#[test]
+fn files_are_stored_back_to_back() {
+    let (_, _, frozen_device) = frozen_device(build_device(&[("a.txt", b"12345"), ("b.txt", b"678")]));
+
+    let mut files: Vec<_> = frozen_device.files.iter().collect();
+    files.sort_by_key(|file| file.data_offset);
+    for pair in files.windows(2) {
+        assert_eq!(pair[0].data_offset + pair[0].data_size, pair[1].data_offset);
+    }
+    let last = files.last().unwrap();
+    assert_eq!(last.data_offset + last.data_size, frozen_device.footer_location);
+}
+
+#[test]
+fn unknown_flags_are_kept() {
+    let file = DeviceFile {
+        file_type: "TEXT".to_string(),
+        file_name: "notes.txt".to_string(),
+        data_size: 3,
+        data_offset: 16,
+        flag: DeviceFileFlag::Other(42),
+        modification_date: utc_time(2024, 5, 1, 12, 0, 0).unwrap(),
+        version: 0,
+    };
+
+    let files = parse_footer(build_footer(&[file])).unwrap();
+    assert_eq!(files[0].flag, DeviceFileFlag::Other(42));
+    assert_eq!(files[0].flag.to_raw(), 42);
+}
+
+#[test]
+fn template_without_a_file_name_is_an_error() {
+    for name in ["", "/", "templates/.."] {
+        let template = build_unfrozen_amxd(&DeviceType::AudioEffect, &0, Bytes::from(PATCHER));
+        let error = DeviceBuilder::new(TemplateSource::Memory { name: name.to_string(), data: template })
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(error.downcast_ref::<BuildError>(), Some(BuildError::UnsupportedFile { .. })), "{}", name);
+    }
+}
+
+#[test]
+fn data_in_memory_is_built_reproducibly() {
+    let build = || {
+        let template = build_unfrozen_amxd(&DeviceType::AudioEffect, &0, Bytes::from(PATCHER));
+        DeviceBuilder::new(TemplateSource::Memory { name: "Dev.amxd".to_string(), data: template })
+            .include_data("notes.txt", b"hi".to_vec(), "<stdin>")
+            .build()
+            .unwrap()
+            .data
+    };
+
+    let first = build();
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(first, build());
+
+    let (_, _, frozen_device) = frozen_device(first);
+    assert!(frozen_device.files.iter().all(|file| file.modification_date == utc_time(1904, 1, 1, 0, 0, 0).unwrap()));
+}
@@ -0,0 +1,61 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+use bytes::Bytes;
+use maxbuild::dependencies::{parse_builtin_objects, DEFAULT_BUILTIN_OBJECTS};
+use maxbuild::{DeviceBuilder, TemplateSource};
+
+/// Objects that nearly every patcher uses, including the short aliases
+const CORE_OBJECTS: &[&str] = &[
+    "autopattr", "b", "bangbang", "buffer~", "coll", "cycle~", "dac~", "adc~", "del", "delay", "dict", "f", "float",
+    "gate", "i", "int", "js", "line", "line~", "live.dial", "live.thisdevice", "loadbang", "metro", "pack",
+    "pattr", "plugin~", "plugout~", "poly~", "prepend", "print", "r", "receive", "route", "s", "scale", "sel",
+    "select", "send", "stripnote", "t", "trigger", "unpack", "v", "value", "zl",
+];
+
+#[test]
+fn default_list_contains_the_core_objects() {
+    let builtin_objects = parse_builtin_objects(DEFAULT_BUILTIN_OBJECTS);
+    let missing: Vec<_> = CORE_OBJECTS.iter().filter(|name| !builtin_objects.contains(**name)).collect();
+
+    assert!(missing.is_empty(), "missing built-in objects: {:?}", missing);
+}
+
+#[test]
+fn comments_and_blank_lines_are_ignored() {
+    let builtin_objects = parse_builtin_objects("# comment\n\n  metro  \r\ncycle~\n");
+
+    assert_eq!(builtin_objects.into_iter().collect::<Vec<_>>(), ["cycle~", "metro"]);
+}
+
+#[test]
+fn aliases_are_not_reported_as_missing() {
+    let patcher = r#"{"patcher": {"project": {"version": 1}, "boxes": [
+        {"box": {"maxclass": "newobj", "text": "i"}},
+        {"box": {"maxclass": "newobj", "text": "f 0.5"}},
+        {"box": {"maxclass": "newobj", "text": "b 2"}},
+        {"box": {"maxclass": "newobj", "text": "int"}}
+    ]}}"#;
+    let search_root = format!("{}/builtin_objects", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::create_dir_all(&search_root).unwrap();
+    let builder = DeviceBuilder::new(TemplateSource::Memory { name: "Dev.maxpat".to_string(), data: Bytes::from(patcher) })
+        .search_root(search_root);
+
+    let report = builder.check_dependencies(&parse_builtin_objects(DEFAULT_BUILTIN_OBJECTS)).unwrap();
+    assert!(report.missing.is_empty());
+    assert!(report.unreferenced.is_empty());
+}
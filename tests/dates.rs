@@ -0,0 +1,83 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+use maxbuild::amxd_footer::{clamp_to_hfsplus_range, to_hfsplus_time, to_hfsplus_time_clamped};
+use maxbuild::device_builder::utc_time;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn utc_time_counts_seconds_since_1970() {
+    assert_eq!(utc_time(1970, 1, 1, 0, 0, 0), Some(SystemTime::UNIX_EPOCH));
+    assert_eq!(utc_time(2000, 3, 1, 0, 0, 1), Some(SystemTime::UNIX_EPOCH + Duration::from_secs(951868801)));
+    assert_eq!(utc_time(2024, 12, 31, 23, 59, 59), Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1735689599)));
+}
+
+#[test]
+fn utc_time_rejects_out_of_range_fields() {
+    assert_eq!(utc_time(2024, 13, 1, 0, 0, 0), None);
+    assert_eq!(utc_time(2024, 0, 1, 0, 0, 0), None);
+    assert_eq!(utc_time(2024, 1, 32, 0, 0, 0), None);
+    assert_eq!(utc_time(2024, 1, 1, 24, 0, 0), None);
+    assert_eq!(utc_time(2024, 1, 1, 0, 60, 0), None);
+}
+
+#[test]
+fn utc_time_rejects_years_that_overflow() {
+    assert_eq!(utc_time(99999999999999, 1, 1, 0, 0, 0), None);
+    assert_eq!(utc_time(i64::MAX, 12, 31, 23, 59, 59), None);
+    assert_eq!(utc_time(i64::MIN, 1, 1, 0, 0, 0), None);
+}
+
+#[test]
+fn utc_time_checks_the_length_of_the_month() {
+    assert_eq!(utc_time(2024, 2, 31, 0, 0, 0), None);
+    assert_eq!(utc_time(2024, 4, 31, 0, 0, 0), None);
+    assert_eq!(utc_time(2023, 2, 29, 0, 0, 0), None);
+    assert_eq!(utc_time(1900, 2, 29, 0, 0, 0), None);
+    assert!(utc_time(2024, 2, 29, 0, 0, 0).is_some());
+    assert!(utc_time(2000, 2, 29, 0, 0, 0).is_some());
+    assert!(utc_time(2024, 12, 31, 0, 0, 0).is_some());
+}
+
+#[test]
+fn hfsplus_time_starts_in_1904() {
+    assert_eq!(to_hfsplus_time(&SystemTime::UNIX_EPOCH), Some(2082844800));
+    assert_eq!(to_hfsplus_time(&utc_time(1904, 1, 1, 0, 0, 0).unwrap()), Some(0));
+    assert_eq!(to_hfsplus_time(&utc_time(1903, 12, 31, 23, 59, 59).unwrap()), None);
+    assert_eq!(to_hfsplus_time(&utc_time(2040, 2, 6, 6, 28, 15).unwrap()), Some(u32::MAX));
+    assert_eq!(to_hfsplus_time(&utc_time(2040, 2, 6, 6, 28, 16).unwrap()), None);
+}
+
+#[test]
+fn hfsplus_time_is_clamped_to_its_range() {
+    let before = utc_time(1900, 1, 1, 0, 0, 0).unwrap();
+    let after = utc_time(2100, 1, 1, 0, 0, 0).unwrap();
+
+    assert_eq!(to_hfsplus_time_clamped(&before), 0);
+    assert_eq!(to_hfsplus_time_clamped(&after), u32::MAX);
+    assert_eq!(clamp_to_hfsplus_range(&before), utc_time(1904, 1, 1, 0, 0, 0).unwrap());
+    assert_eq!(clamp_to_hfsplus_range(&after), utc_time(2040, 2, 6, 6, 28, 15).unwrap());
+
+    let in_range = utc_time(2024, 5, 1, 12, 0, 0).unwrap();
+    assert_eq!(clamp_to_hfsplus_range(&in_range), in_range);
+}
+
+#[test]
+fn hfsplus_time_rounds_down_before_1970() {
+    let time = SystemTime::UNIX_EPOCH - Duration::from_millis(500);
+    assert_eq!(to_hfsplus_time(&time), Some(2082844799));
+}
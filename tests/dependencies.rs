@@ -0,0 +1,159 @@
+/*
+ *  Copyright 2025 Mikerooni
+ *  
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *  
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *  
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+
+
+use maxbuild::dependencies::{find_references, resolve_dependencies, DependencyKind, KnownFile, SearchPath};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+
+fn patcher(boxes: Value) -> Value {
+    json!({"patcher": {"boxes": boxes}})
+}
+
+fn references_of(patcher_json: &Value) -> Vec<(DependencyKind, Vec<String>)> {
+    find_references(patcher_json).into_iter()
+        .map(|reference| (reference.kind, reference.candidates))
+        .collect()
+}
+
+fn candidates(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// Writes the given files to a fresh directory and returns their paths
+fn write_files(directory: &str, files: &[(&str, Value)]) -> Vec<PathBuf> {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(directory);
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    files.iter()
+        .map(|(name, contents)| {
+            let path = directory.join(name);
+            fs::write(&path, contents.to_string()).unwrap();
+            path
+        })
+        .collect()
+}
+
+#[test]
+fn gen_patchers_are_named_by_the_gen_attribute() {
+    let references = references_of(&patcher(json!([
+        {"box": {"maxclass": "newobj", "text": "gen~ @gen osc @title Osc"}},
+        {"box": {"maxclass": "newobj", "text": "jit.gl.pix ctx @gen blur.genjit"}},
+        {"box": {"maxclass": "newobj", "text": "gen~ filter"}},
+        {"box": {"maxclass": "newobj", "text": "gen~", "patcher": {"boxes": [
+            {"box": {"maxclass": "newobj", "text": "in 1"}}
+        ]}}}
+    ])));
+
+    assert_eq!(references, [
+        (DependencyKind::Gen, candidates(&["osc.gendsp"])),
+        (DependencyKind::Gen, candidates(&["blur.genjit"])),
+        (DependencyKind::Gen, candidates(&["filter.gendsp"])),
+    ]);
+}
+
+#[test]
+fn bpatcher_and_poly_patchers_are_abstractions() {
+    let references = references_of(&patcher(json!([
+        {"box": {"maxclass": "bpatcher", "name": "../ui/panel.maxpat"}},
+        {"box": {"maxclass": "bpatcher", "name": "knobs"}},
+        {"box": {"maxclass": "newobj", "text": "poly~ voice 8 @parallel 1"}},
+        {"box": {"maxclass": "newobj", "text": "poly~ @steal 1"}}
+    ])));
+
+    assert_eq!(references, [
+        (DependencyKind::Abstraction, candidates(&["panel.maxpat"])),
+        (DependencyKind::Abstraction, candidates(&["knobs.maxpat"])),
+        (DependencyKind::Abstraction, candidates(&["voice.maxpat"])),
+    ]);
+}
+
+#[test]
+fn media_arguments_are_filtered_by_extension() {
+    let references = references_of(&patcher(json!([
+        {"box": {"maxclass": "newobj", "text": "buffer~ drums drums.wav 2"}},
+        {"box": {"maxclass": "newobj", "text": "sfplay~ 2 loop.aif"}},
+        {"box": {"maxclass": "newobj", "text": "buffer~ notes notes.txt"}}
+    ])));
+    // Every object box is also a possible abstraction, built-in objects are only filtered out later
+    let references: Vec<_> = references.into_iter().filter(|(kind, _)| *kind == DependencyKind::Media).collect();
+
+    assert_eq!(references, [
+        (DependencyKind::Media, candidates(&["drums.wav"])),
+        (DependencyKind::Media, candidates(&["loop.aif"])),
+    ]);
+}
+
+#[test]
+fn image_attributes_may_hold_several_pictures() {
+    let references = references_of(&patcher(json!([
+        {"box": {"maxclass": "live.tab", "pictures": ["on.png", "off.png"]}},
+        {"box": {"maxclass": "fpic", "pic": "/Users/me/background.jpg"}},
+        {"box": {"maxclass": "pictctrl", "name": "knob.png"}}
+    ])));
+
+    assert_eq!(references, [
+        (DependencyKind::Image, candidates(&["on.png"])),
+        (DependencyKind::Image, candidates(&["off.png"])),
+        (DependencyKind::Image, candidates(&["background.jpg"])),
+        (DependencyKind::Image, candidates(&["knob.png"])),
+    ]);
+}
+
+#[test]
+fn patchers_on_the_search_path_are_followed() {
+    let paths = write_files("dependencies_search_path", &[
+        ("outer.maxpat", patcher(json!([{"box": {"maxclass": "newobj", "text": "inner"}}]))),
+        ("inner.maxpat", patcher(json!([{"box": {"maxclass": "newobj", "text": "js helper.js"}}]))),
+        ("helper.js", json!("post();")),
+    ]);
+    let main_patcher = patcher(json!([{"box": {"maxclass": "newobj", "text": "outer"}}]));
+
+    let closure = resolve_dependencies(&[main_patcher], &[], &SearchPath::new(paths.clone()));
+
+    let found: Vec<_> = closure.found.iter().map(|(_, path)| path.clone()).collect();
+    assert_eq!(found, paths);
+    assert!(closure.unresolved.is_empty());
+    assert!(closure.known.is_empty());
+}
+
+#[test]
+fn known_files_take_precedence_over_the_search_path() {
+    let paths = write_files("dependencies_precedence", &[
+        ("voice.maxpat", patcher(json!([{"box": {"maxclass": "newobj", "text": "from_search_path"}}]))),
+        ("filter.maxpat", patcher(json!([]))),
+    ]);
+    let known_files = [KnownFile {
+        name: "Voice.maxpat".to_string(),
+        patcher: Some(patcher(json!([{"box": {"maxclass": "newobj", "text": "filter"}}]))),
+    }];
+    let main_patcher = patcher(json!([
+        {"box": {"maxclass": "newobj", "text": "poly~ voice 4"}},
+        {"box": {"maxclass": "newobj", "text": "missing"}}
+    ]));
+
+    let closure = resolve_dependencies(&[main_patcher], &known_files, &SearchPath::new(paths.clone()));
+
+    let known: Vec<_> = closure.known.iter().map(|(reference, name)| (reference.name.as_str(), name.as_str())).collect();
+    assert_eq!(known, [("voice", "Voice.maxpat")]);
+    let found: Vec<_> = closure.found.iter().map(|(_, path)| path.clone()).collect();
+    assert_eq!(found, [paths[1].clone()]);
+    let unresolved: Vec<_> = closure.unresolved.iter().map(|reference| reference.name.as_str()).collect();
+    assert_eq!(unresolved, ["missing"]);
+}